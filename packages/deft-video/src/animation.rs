@@ -0,0 +1,305 @@
+use crate::export::{open_at, write_packets, ClipExporter, Progress};
+use crate::source::MediaSource;
+use crate::video_filter::VideoFilter;
+use anyhow::anyhow;
+use ffmpeg_next::format::context::Output;
+use ffmpeg_next::{codec, encoder, format, frame, media, picture, Codec, Rational};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnimationFormat {
+    /// 256 colour palette generated from the whole range
+    Gif,
+    /// Full colour, lossy
+    Webp,
+}
+
+impl AnimationFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        let format = match value.trim().to_ascii_lowercase().as_str() {
+            "gif" => Self::Gif,
+            "webp" => Self::Webp,
+            _ => return None,
+        };
+        Some(format)
+    }
+
+    fn muxer(self) -> &'static str {
+        match self {
+            Self::Gif => "gif",
+            Self::Webp => "webp",
+        }
+    }
+
+    fn encoder(self) -> Option<Codec> {
+        match self {
+            Self::Gif => encoder::find(codec::Id::GIF),
+            Self::Webp => encoder::find_by_name("libwebp_anim"),
+        }
+    }
+}
+
+pub struct AnimationParams {
+    pub src: MediaSource,
+    pub output: String,
+    /// Range to export in seconds
+    pub start: f32,
+    pub end: f32,
+    /// Frames per second of the animation
+    pub fps: f32,
+    /// Width in pixels, the height keeps the aspect ratio. 0 keeps the source width
+    pub width: u32,
+    pub format: AnimationFormat,
+    /// libavfilter description applied to the video before scaling
+    pub video_filter: Option<String>,
+    pub on_progress: Box<dyn FnMut(f32) + Send + 'static>,
+    pub on_done: Box<dyn FnOnce(Result<String, anyhow::Error>) + Send + 'static>,
+}
+
+impl ClipExporter {
+    /// Write a range of the source as an animated image
+    pub fn start_animation(params: AnimationParams) -> Self {
+        let AnimationParams {
+            src,
+            output,
+            start,
+            end,
+            fps,
+            width,
+            format,
+            video_filter,
+            on_progress,
+            on_done,
+        } = params;
+        let spec = animation_filter(format, fps, width, video_filter.as_deref());
+        Self::spawn(
+            output,
+            (start, end),
+            on_progress,
+            on_done,
+            move |output, range, progress| {
+                write_animation(src, output, range, format, fps, &spec, progress)
+            },
+        )
+    }
+}
+
+/// Graph resampling to `fps` and scaling to `width`, quantised to a palette for GIF
+fn animation_filter(
+    format: AnimationFormat,
+    fps: f32,
+    width: u32,
+    video_filter: Option<&str>,
+) -> String {
+    let mut chain = Vec::new();
+    if let Some(spec) = video_filter {
+        chain.push(spec.to_string());
+    }
+    chain.push(format!("fps={}", fps));
+    if width > 0 {
+        chain.push(format!("scale={}:-2:flags=lanczos", width));
+    }
+    match format {
+        // The palette is computed once the whole range has been seen, so frames are only
+        // released when the graph is flushed
+        AnimationFormat::Gif => chain.push(
+            "split[frames][stats];[stats]palettegen=stats_mode=full[palette];\
+             [frames][palette]paletteuse=dither=sierra2_4a"
+                .to_string(),
+        ),
+        AnimationFormat::Webp => chain.push("format=yuva420p".to_string()),
+    }
+    chain.join(",")
+}
+
+/// Filter and encoder, created once the first frame of the range is decoded
+struct AnimationEncoder {
+    filter: VideoFilter,
+    encoder: encoder::video::Encoder,
+    output_index: usize,
+    /// Output time base of the filter, 1/fps after resampling
+    time_base: Rational,
+    /// Filtered pts of `start`, subtracted from the output timestamps
+    start_pts: i64,
+}
+
+fn write_animation(
+    src: MediaSource,
+    output: &str,
+    (start, end): (f64, f64),
+    format: AnimationFormat,
+    fps: f32,
+    spec: &str,
+    progress: &mut Progress,
+) -> Result<(), anyhow::Error> {
+    let mut input = open_at(src, start, progress.interrupter())?;
+    let mut octx = format::output_as(&output, format.muxer())
+        .map_err(|e| anyhow!("cannot create {}: {}", output, e))?;
+    let stream = input
+        .streams()
+        .best(media::Type::Video)
+        .ok_or(anyhow!("no video stream"))?;
+    let input_index = stream.index();
+    let time_base = stream.time_base();
+    let mut decoder = codec::Context::from_parameters(stream.parameters())?
+        .decoder()
+        .video()?;
+
+    let mut animation: Option<AnimationEncoder> = None;
+    let mut decoded = frame::Video::empty();
+    let mut done = false;
+    while !done {
+        let packet = input.read_packet()?;
+        match &packet {
+            Some(packet) if packet.stream() == input_index => decoder.send_packet(packet)?,
+            Some(_) => continue,
+            None => decoder.send_eof()?,
+        }
+        while decoder.receive_frame(&mut decoded).is_ok() {
+            let time = decoded.pts().unwrap_or(0) as f64 * f64::from(time_base);
+            // Decoding starts at the keyframe before start
+            if time < start {
+                continue;
+            }
+            if time > end {
+                done = true;
+                break;
+            }
+            if animation.is_none() {
+                animation = Some(create_encoder(
+                    &mut octx,
+                    &decoded,
+                    format,
+                    fps,
+                    spec,
+                    (time_base, start),
+                )?);
+            }
+            let current = animation.as_mut().unwrap();
+            current.filter.push(&decoded)?;
+            current.write_filtered(&mut octx, end, progress)?;
+            progress.update(time)?;
+        }
+        done |= packet.is_none();
+    }
+    let Some(mut animation) = animation else {
+        return Err(anyhow!("no frames in range"));
+    };
+    animation.filter.flush();
+    animation.write_filtered(&mut octx, end, progress)?;
+    animation.encoder.send_eof()?;
+    write_packets(
+        &mut animation.encoder,
+        animation.time_base,
+        animation.output_index,
+        &mut octx,
+    )?;
+    octx.write_trailer()?;
+    progress.finish();
+    Ok(())
+}
+
+fn create_encoder(
+    octx: &mut Output,
+    first_frame: &frame::Video,
+    format: AnimationFormat,
+    fps: f32,
+    spec: &str,
+    (input_time_base, start): (Rational, f64),
+) -> Result<AnimationEncoder, anyhow::Error> {
+    let codec = format
+        .encoder()
+        .ok_or(anyhow!("no {} encoder available", format.muxer()))?;
+    let filter = VideoFilter::new(spec, first_frame, input_time_base)?;
+    let (pixel_format, width, height) = filter.output_format();
+    let time_base = filter.output_time_base();
+
+    let mut ost = octx.add_stream(codec)?;
+    let output_index = ost.index();
+    let mut context = codec::context::Context::new_with_codec(codec)
+        .encoder()
+        .video()?;
+    context.set_width(width);
+    context.set_height(height);
+    context.set_format(pixel_format);
+    context.set_time_base(time_base);
+    context.set_frame_rate(Some(Rational::from(fps as f64)));
+    let encoder = context.open_as(codec)?;
+    ost.set_parameters(&encoder);
+    ost.set_time_base(time_base);
+    octx.write_header()?;
+    Ok(AnimationEncoder {
+        filter,
+        encoder,
+        output_index,
+        time_base,
+        start_pts: (start / f64::from(time_base)) as i64,
+    })
+}
+
+impl AnimationEncoder {
+    fn write_filtered(
+        &mut self,
+        octx: &mut Output,
+        end: f64,
+        progress: &mut Progress,
+    ) -> Result<(), anyhow::Error> {
+        while let Some(mut filtered) = self.filter.pull() {
+            let pts = filtered.pts().unwrap_or(0);
+            let time = pts as f64 * f64::from(self.time_base);
+            if time > end {
+                continue;
+            }
+            filtered.set_pts(Some(pts - self.start_pts));
+            filtered.set_kind(picture::Type::None);
+            self.encoder.send_frame(&filtered)?;
+            write_packets(&mut self.encoder, self.time_base, self.output_index, octx)?;
+            progress.update(time)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_media::{decode_video, temp_dir, TestVideo};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn gif_is_resampled_and_scaled() {
+        let dir = temp_dir("animation-gif");
+        let path = dir.join("video.mkv");
+        TestVideo {
+            frames: 100,
+            ..TestVideo::default()
+        }
+        .write(&path, None, &[]);
+        let output = dir.join("clip.gif");
+        let (sender, receiver) = mpsc::channel();
+        let _exporter = ClipExporter::start_animation(AnimationParams {
+            src: MediaSource::Url(path.to_str().unwrap().to_string()),
+            output: output.to_str().unwrap().to_string(),
+            start: 1.0,
+            end: 2.0,
+            fps: 10.0,
+            width: 32,
+            format: AnimationFormat::Gif,
+            video_filter: None,
+            on_progress: Box::new(|_| {}),
+            on_done: Box::new(move |result| sender.send(result).unwrap()),
+        });
+        receiver.recv_timeout(Duration::from_secs(30)).unwrap().unwrap();
+
+        let frames = decode_video(&output);
+        assert!((10..=11).contains(&frames.len()), "{} frames", frames.len());
+        for (_, frame) in &frames {
+            assert_eq!((frame.width(), frame.height()), (32, 24));
+        }
+        // 1/10s apart, starting at 0
+        assert!(frames[0].0 < 0.01, "starts at {}", frames[0].0);
+        for pair in frames.windows(2) {
+            assert!((pair[1].0 - pair[0].0 - 0.1).abs() < 0.011, "{:?}", (pair[0].0, pair[1].0));
+        }
+    }
+}
@@ -0,0 +1,145 @@
+/// Shift of the audio output against the video, kept in whole frames (one sample per
+/// channel) so that interleaved channels never swap
+#[derive(Default)]
+pub struct AudioDelay {
+    /// Frames the queued audio has been shifted by so far
+    applied_frames: i64,
+    /// Frames still to be dropped to play the audio earlier
+    skip_frames: usize,
+}
+
+impl AudioDelay {
+    /// Shift to `delay_ms` at `rate`, returns the frames of silence to queue now. Playing
+    /// earlier is done by `skip` on the following samples
+    pub fn set(&mut self, delay_ms: i32, rate: i64) -> usize {
+        // Rounded from the total delay so that repeated small changes do not drift
+        let target = (delay_ms as f64 * rate as f64 / 1000.0).round() as i64;
+        let change = target - self.applied_frames;
+        self.applied_frames = target;
+        if change < 0 {
+            self.skip_frames += change.unsigned_abs() as usize;
+            return 0;
+        }
+        // Frames not dropped yet cancel out with the silence
+        let silence = change as usize;
+        let cancelled = silence.min(self.skip_frames);
+        self.skip_frames -= cancelled;
+        silence - cancelled
+    }
+
+    /// Frames to drop from the start of the next `frames` frames
+    pub fn skip(&mut self, frames: usize) -> usize {
+        let skipped = self.skip_frames.min(frames);
+        self.skip_frames -= skipped;
+        skipped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANNELS: usize = 2;
+
+    /// Feed `frames` stereo frames in chunks of `chunk` through the delay, changing it to
+    /// `delay_ms` once `at` frames have been fed. Left samples are 2n and right 2n+1 for
+    /// frame n, silence is -1
+    fn play(rate: i64, frames: usize, chunk: usize, changes: &[(usize, i32)]) -> Vec<i64> {
+        let mut delay = AudioDelay::default();
+        let mut output = Vec::new();
+        for start in (0..frames).step_by(chunk) {
+            for &(_, delay_ms) in changes.iter().filter(|(at, _)| *at == start) {
+                let silence = delay.set(delay_ms, rate);
+                output.extend(std::iter::repeat(-1).take(silence * CHANNELS));
+            }
+            let end = (start + chunk).min(frames);
+            let skipped = delay.skip(end - start);
+            for frame in start + skipped..end {
+                output.extend([2 * frame as i64, 2 * frame as i64 + 1]);
+            }
+        }
+        output
+    }
+
+    fn assert_channels_aligned(output: &[i64]) {
+        assert_eq!(output.len() % CHANNELS, 0);
+        for pair in output.chunks(CHANNELS) {
+            assert!(
+                pair == [-1, -1] || (pair[0] % 2 == 0 && pair[1] == pair[0] + 1),
+                "channels swapped: {:?}",
+                pair
+            );
+        }
+    }
+
+    /// Output position of input frame `frame`
+    fn position(output: &[i64], frame: i64) -> usize {
+        output.iter().position(|&s| s == 2 * frame).unwrap() / CHANNELS
+    }
+
+    #[test]
+    fn delay_inserts_whole_frames_of_silence() {
+        // 7ms at 44.1kHz is 308.7 frames
+        let output = play(44100, 1000, 100, &[(0, 7)]);
+        assert_channels_aligned(&output);
+        assert_eq!(position(&output, 0), 309);
+    }
+
+    #[test]
+    fn advance_drops_whole_frames() {
+        let output = play(44100, 1000, 100, &[(200, -3)]);
+        assert_channels_aligned(&output);
+        // 132.3 frames are dropped from frame 200 on
+        assert_eq!(position(&output, 199), 199);
+        assert_eq!(position(&output, 332), 200);
+        assert!(!output.contains(&(2 * 331)));
+    }
+
+    #[test]
+    fn drops_span_chunks() {
+        let output = play(48000, 1000, 64, &[(0, -5)]);
+        assert_channels_aligned(&output);
+        assert_eq!(position(&output, 240), 0);
+    }
+
+    #[test]
+    fn small_changes_do_not_drift() {
+        let changes: Vec<(usize, i32)> = (1..=10).map(|ms| (ms as usize * 10, ms)).collect();
+        let output = play(44100, 1000, 10, &changes);
+        assert_channels_aligned(&output);
+        // 10ms is 441 frames, truncating each 1ms step would only give 440
+        assert_eq!(position(&output, 999) - 999, 441);
+    }
+
+    #[test]
+    fn delay_is_reapplied_after_seek() {
+        let rate = 48000;
+        let mut delay = AudioDelay::default();
+        assert_eq!(delay.set(10, rate), 480);
+        assert_eq!(delay.set(10, rate), 0);
+        // A seek discards the queued samples along with the silence, as the player does
+        delay = AudioDelay::default();
+        assert_eq!(delay.set(10, rate), 480);
+        assert_eq!(delay.skip(1024), 0);
+    }
+
+    #[test]
+    fn advance_is_reapplied_after_seek() {
+        let mut delay = AudioDelay::default();
+        assert_eq!(delay.set(-5, 48000), 0);
+        assert_eq!(delay.skip(1024), 240);
+        delay = AudioDelay::default();
+        assert_eq!(delay.set(-5, 48000), 0);
+        assert_eq!(delay.skip(1024), 240);
+    }
+
+    #[test]
+    fn silence_cancels_pending_drops() {
+        // The drop has not happened when the delay is restored within the same chunk
+        let mut delay = AudioDelay::default();
+        assert_eq!(delay.set(-10, 48000), 0);
+        assert_eq!(delay.set(0, 48000), 0);
+        assert_eq!(delay.skip(1024), 0);
+        assert_eq!(delay.set(5, 48000), 240);
+    }
+}
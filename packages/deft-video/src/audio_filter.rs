@@ -0,0 +1,130 @@
+use ffmpeg_next::decoder::Audio;
+use ffmpeg_next::filter::Graph;
+use ffmpeg_next::format::{sample, Sample};
+use ffmpeg_next::{filter, frame, ChannelLayout};
+
+/// Sample format, rate and channel layout of audio frames
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AudioFormat {
+    pub format: Sample,
+    pub rate: u32,
+    pub channel_layout: ChannelLayout,
+}
+
+impl AudioFormat {
+    pub fn of_frame(frame: &frame::Audio) -> Self {
+        Self::new(
+            frame.format(),
+            frame.rate(),
+            frame.channel_layout(),
+            frame.channels(),
+        )
+    }
+
+    pub fn of_decoder(decoder: &Audio) -> Self {
+        Self::new(
+            decoder.format(),
+            decoder.rate(),
+            decoder.channel_layout(),
+            decoder.channels(),
+        )
+    }
+
+    fn new(format: Sample, rate: u32, channel_layout: ChannelLayout, channels: u16) -> Self {
+        // Some decoders only report the channel count
+        let channel_layout = if channel_layout.is_empty() {
+            ChannelLayout::default(channels as i32)
+        } else {
+            channel_layout
+        };
+        Self {
+            format,
+            rate,
+            channel_layout,
+        }
+    }
+}
+
+/// libavfilter graph applied to decoded audio before resampling, e.g. `dynaudnorm` or
+/// `equalizer=f=1000:t=q:w=1:g=-6`
+pub struct AudioFilter {
+    graph: Graph,
+    input: AudioFormat,
+    flushed: bool,
+}
+
+impl AudioFilter {
+    /// Build a graph from `spec` for audio in `input` format
+    pub fn new(spec: &str, input: AudioFormat) -> Result<Self, anyhow::Error> {
+        let args = format!(
+            "time_base=1/{}:sample_rate={}:sample_fmt={}:channel_layout=0x{:x}",
+            input.rate,
+            input.rate,
+            input.format.name(),
+            input.channel_layout.bits(),
+        );
+        let mut graph = Graph::new();
+        graph.add(&filter::find("abuffer").unwrap(), "in", &args)?;
+        graph.add(&filter::find("abuffersink").unwrap(), "out", "")?;
+        graph.output("in", 0)?.input("out", 0)?.parse(spec)?;
+        graph.validate()?;
+        Ok(Self {
+            graph,
+            input,
+            flushed: false,
+        })
+    }
+
+    /// Build `spec` for typical audio, to report an invalid description before the format
+    /// of a source is known
+    pub fn check(spec: &str) -> Result<(), anyhow::Error> {
+        let format = AudioFormat {
+            format: Sample::F32(sample::Type::Packed),
+            rate: 48000,
+            channel_layout: ChannelLayout::STEREO,
+        };
+        Self::new(spec, format)?;
+        Ok(())
+    }
+
+    /// Whether the graph has been built for audio in `format`
+    pub fn accepts(&self, format: AudioFormat) -> bool {
+        self.input == format
+    }
+
+    /// Make every filtered frame hold `samples` samples, as required by most encoders
+    pub fn set_frame_size(&mut self, samples: u32) {
+        self.graph
+            .get("out")
+            .unwrap()
+            .sink()
+            .set_frame_size(samples);
+    }
+
+    pub fn push(&mut self, frame: &frame::Audio) -> Result<(), anyhow::Error> {
+        self.graph.get("in").unwrap().source().add(frame)?;
+        Ok(())
+    }
+
+    /// Signal the end of input so that samples held back by the graph are released, false if
+    /// it was already flushed
+    pub fn flush(&mut self) -> bool {
+        if self.flushed {
+            return false;
+        }
+        self.flushed = true;
+        self.graph.get("in").unwrap().source().flush().is_ok()
+    }
+
+    /// Next filtered frame, its format may differ from the input
+    pub fn pull(&mut self) -> Option<frame::Audio> {
+        let mut filtered = frame::Audio::empty();
+        self.graph
+            .get("out")
+            .unwrap()
+            .sink()
+            .frame(&mut filtered)
+            .ok()?;
+        Some(filtered)
+    }
+}
@@ -0,0 +1,318 @@
+use crate::interrupt::{Interrupted, Interrupter};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// Bytes kept behind the read position so that the demuxer can seek back a little
+const RETAINED_BYTES: u64 = 8 * 1024 * 1024;
+/// How often a blocked reader checks for control requests
+const INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Bytes appended piecemeal (e.g. from JS) and demuxed while they arrive.
+/// Data more than `RETAINED_BYTES` behind the reader is discarded, seeking back further fails.
+#[derive(Clone, Default)]
+pub struct ByteStream {
+    shared: Arc<(Mutex<StreamBuffer>, Condvar)>,
+}
+
+#[derive(Default)]
+struct StreamBuffer {
+    data: Vec<u8>,
+    /// Stream offset of the first byte of `data`
+    start: u64,
+    /// No more data will be appended, reading past the end returns EOF
+    ended: bool,
+    /// The stream was abandoned, pending and future reads fail
+    closed: bool,
+}
+
+pub struct ByteStreamReader {
+    stream: ByteStream,
+    pos: u64,
+    /// A waiting read fails with `Interrupted` once a control request is pending
+    interrupter: Interrupter,
+    on_waiting: Box<dyn FnMut() + Send + 'static>,
+    on_can_play: Box<dyn FnMut() + Send + 'static>,
+}
+
+impl ByteStream {
+    pub fn append(&self, data: &[u8]) {
+        self.update(|buffer| buffer.data.extend_from_slice(data));
+    }
+
+    pub fn end(&self) {
+        self.update(|buffer| buffer.ended = true);
+    }
+
+    /// Wake up and fail a blocked reader, e.g. so that the player thread can exit
+    pub fn close(&self) {
+        self.update(|buffer| buffer.closed = true);
+    }
+
+    /// Reader that blocks until data is available, `on_waiting` is called when it starts
+    /// waiting and `on_can_play` when the data it waited for has arrived. Waits are cut short
+    /// by requests of the player's `interrupter`
+    pub fn reader(
+        &self,
+        interrupter: Interrupter,
+        on_waiting: Box<dyn FnMut() + Send + 'static>,
+        on_can_play: Box<dyn FnMut() + Send + 'static>,
+    ) -> ByteStreamReader {
+        ByteStreamReader {
+            stream: self.clone(),
+            pos: 0,
+            interrupter,
+            on_waiting,
+            on_can_play,
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut StreamBuffer)) {
+        let (lock, condvar) = &*self.shared;
+        f(&mut lock.lock().unwrap());
+        condvar.notify_all();
+    }
+}
+
+impl StreamBuffer {
+    /// Stream offset after the last appended byte
+    fn end(&self) -> u64 {
+        self.start + self.data.len() as u64
+    }
+
+    /// Free the data before `offset`, in batches so that the copy is amortised
+    fn discard_before(&mut self, offset: u64) {
+        let discardable = offset.saturating_sub(self.start);
+        if discardable < RETAINED_BYTES {
+            return;
+        }
+        self.data.drain(..discardable as usize);
+        self.start = offset;
+    }
+}
+
+impl Read for ByteStreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let (lock, condvar) = &*self.stream.shared;
+        let mut buffer = lock.lock().unwrap();
+        let mut waited = false;
+        let size = loop {
+            if buffer.closed {
+                return Err(Error::new(ErrorKind::BrokenPipe, "stream closed"));
+            }
+            if self.pos < buffer.start {
+                return Err(Error::new(ErrorKind::InvalidInput, "data no longer buffered"));
+            }
+            if self.pos < buffer.end() {
+                let start = (self.pos - buffer.start) as usize;
+                let size = buf.len().min(buffer.data.len() - start);
+                buf[..size].copy_from_slice(&buffer.data[start..start + size]);
+                self.pos += size as u64;
+                buffer.discard_before(self.pos.saturating_sub(RETAINED_BYTES));
+                break size;
+            }
+            if buffer.ended {
+                break 0;
+            }
+            // Stop, seek and load must not wait for data that may never come
+            if self.interrupter.has_pending() {
+                return Err(Error::new(ErrorKind::Other, Interrupted));
+            }
+            if !waited {
+                waited = true;
+                (self.on_waiting)();
+            }
+            buffer = condvar
+                .wait_timeout(buffer, INTERRUPT_POLL_INTERVAL)
+                .unwrap()
+                .0;
+        };
+        drop(buffer);
+        if waited {
+            (self.on_can_play)();
+        }
+        Ok(size)
+    }
+}
+
+impl Seek for ByteStreamReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let (lock, _) = &*self.stream.shared;
+        let buffer = lock.lock().unwrap();
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+            // The size is unknown until the stream has ended
+            SeekFrom::End(offset) if buffer.ended => buffer.end().checked_add_signed(offset),
+            SeekFrom::End(_) => {
+                return Err(Error::new(ErrorKind::Unsupported, "stream size unknown"));
+            }
+        };
+        let target = target.ok_or(Error::new(ErrorKind::InvalidInput, "invalid seek position"))?;
+        if target < buffer.start {
+            return Err(Error::new(ErrorKind::InvalidInput, "data no longer buffered"));
+        }
+        self.pos = target;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::{MediaSource, OpenOptions, SourceInput};
+    use crate::test_media::{temp_dir, TestVideo};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn reader_blocks_until_appended() {
+        let stream = ByteStream::default();
+        let waits = Arc::new(AtomicUsize::new(0));
+        let resumes = Arc::new(AtomicUsize::new(0));
+        let mut reader = {
+            let (waits, resumes) = (waits.clone(), resumes.clone());
+            stream.reader(
+                Interrupter::default(),
+                Box::new(move || {
+                    waits.fetch_add(1, Ordering::SeqCst);
+                }),
+                Box::new(move || {
+                    resumes.fetch_add(1, Ordering::SeqCst);
+                }),
+            )
+        };
+        let feeder = {
+            let stream = stream.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                stream.append(b"abc");
+                stream.end();
+            })
+        };
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        feeder.join().unwrap();
+        assert_eq!(data, b"abc");
+        assert_eq!(waits.load(Ordering::SeqCst), 1);
+        assert_eq!(resumes.load(Ordering::SeqCst), 1);
+        // The size is known once ended
+        assert_eq!(reader.seek(SeekFrom::End(-1)).unwrap(), 2);
+    }
+
+    #[test]
+    fn close_fails_blocked_reader() {
+        let stream = ByteStream::default();
+        let mut reader = stream.reader(Interrupter::default(), Box::new(|| {}), Box::new(|| {}));
+        assert!(reader.seek(SeekFrom::End(0)).is_err());
+        let closer = {
+            let stream = stream.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                stream.close();
+            })
+        };
+        let error = reader.read(&mut [0; 16]).unwrap_err();
+        closer.join().unwrap();
+        assert_eq!(error.kind(), ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn interrupt_fails_blocked_reader() {
+        let stream = ByteStream::default();
+        let interrupter = Interrupter::default();
+        let mut reader = stream.reader(interrupter.clone(), Box::new(|| {}), Box::new(|| {}));
+        let requester = {
+            let interrupter = interrupter.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                interrupter.request();
+            })
+        };
+        let error = reader.read(&mut [0; 16]).unwrap_err();
+        requester.join().unwrap();
+        assert!(error.get_ref().unwrap().is::<Interrupted>());
+        // Reading waits again once the request has been handled
+        interrupter.acknowledge();
+        stream.append(b"abc");
+        let mut data = [0; 3];
+        reader.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"abc");
+    }
+
+    #[test]
+    fn read_data_is_discarded() {
+        let stream = ByteStream::default();
+        let mut reader = stream.reader(Interrupter::default(), Box::new(|| {}), Box::new(|| {}));
+        let chunk = vec![7; 1024 * 1024];
+        let mut buf = vec![0; chunk.len()];
+        for _ in 0..RETAINED_BYTES * 4 / chunk.len() as u64 {
+            stream.append(&chunk);
+            reader.read_exact(&mut buf).unwrap();
+        }
+        let kept = stream.shared.0.lock().unwrap().data.len() as u64;
+        assert!(kept <= RETAINED_BYTES * 2, "{} bytes kept", kept);
+        // Recent data can be read again, the start of the stream is gone
+        let pos = reader.stream_position().unwrap();
+        let recent = pos - RETAINED_BYTES;
+        assert_eq!(reader.seek(SeekFrom::Start(recent)).unwrap(), recent);
+        assert!(reader.seek(SeekFrom::Start(0)).is_err());
+    }
+
+    /// A file pushed in chunks, like `openStream`, `appendBuffer` and `endOfStream` from JS
+    #[test]
+    fn demux_file_fed_in_chunks() {
+        let dir = temp_dir("byte-stream");
+        let path = dir.join("chunks.mkv");
+        let video = TestVideo::default();
+        video.write(&path, None, &[]);
+        let bytes = std::fs::read(&path).unwrap();
+
+        let stream = ByteStream::default();
+        let waits = Arc::new(AtomicUsize::new(0));
+        let resumes = Arc::new(AtomicUsize::new(0));
+        let reader = {
+            let (waits, resumes) = (waits.clone(), resumes.clone());
+            stream.reader(
+                Interrupter::default(),
+                Box::new(move || {
+                    waits.fetch_add(1, Ordering::SeqCst);
+                }),
+                Box::new(move || {
+                    resumes.fetch_add(1, Ordering::SeqCst);
+                }),
+            )
+        };
+        let feeder = {
+            let stream = stream.clone();
+            thread::spawn(move || {
+                for chunk in bytes.chunks(1024) {
+                    stream.append(chunk);
+                    thread::sleep(Duration::from_millis(2));
+                }
+                stream.end();
+            })
+        };
+        let mut input = SourceInput::open(
+            MediaSource::Reader(Box::new(reader)),
+            OpenOptions::default(),
+            Interrupter::default(),
+        )
+        .unwrap();
+        // Pushed streams are not live, even before their size is known
+        assert!(!input.is_live());
+        let mut packets = 0;
+        while let Some(_packet) = input.read_packet().unwrap() {
+            packets += 1;
+        }
+        feeder.join().unwrap();
+        assert_eq!(packets, video.frames);
+        // Reading outpaced the feeder, every wait ended with data
+        assert!(waits.load(Ordering::SeqCst) > 0);
+        assert_eq!(waits.load(Ordering::SeqCst), resumes.load(Ordering::SeqCst));
+        // Read again after the end of the stream
+        assert!(input.read_packet().unwrap().is_none());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,762 @@
+use crate::audio_filter::{AudioFilter, AudioFormat};
+use crate::interrupt::Interrupter;
+use crate::source::{MediaSource, OpenOptions, SourceInput};
+use crate::video_filter::VideoFilter;
+use anyhow::anyhow;
+use ffmpeg_next::ffi::AV_TIME_BASE;
+use ffmpeg_next::format::context::Output;
+use ffmpeg_next::format::Pixel;
+use ffmpeg_next::{
+    codec, decoder, encoder, format, frame, media, picture, Dictionary, Packet, Rational,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportMode {
+    /// Remux the packets, the clip starts at the keyframe before `start`
+    Copy,
+    /// Decode, filter and encode, the clip starts exactly at `start`
+    Encode,
+}
+
+impl ExportMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        let mode = match value.trim().to_ascii_lowercase().as_str() {
+            "copy" => Self::Copy,
+            "encode" => Self::Encode,
+            _ => return None,
+        };
+        Some(mode)
+    }
+}
+
+pub struct ExportParams {
+    pub src: MediaSource,
+    /// Output path, the container is taken from the extension, e.g. mp4 or mkv
+    pub output: String,
+    /// Range to export in seconds
+    pub start: f32,
+    pub end: f32,
+    pub mode: ExportMode,
+    /// libavfilter description applied to the video when encoding
+    pub video_filter: Option<String>,
+    pub on_progress: Box<dyn FnMut(f32) + Send + 'static>,
+    pub on_done: Box<dyn FnOnce(Result<String, anyhow::Error>) + Send + 'static>,
+}
+
+/// Writes a range of a source to a new file on a background thread.
+/// Dropping the exporter cancels it and waits for the thread to exit.
+pub struct ClipExporter {
+    cancelled: Arc<AtomicBool>,
+    /// Aborts an open or read blocked on a stalled network source
+    interrupter: Interrupter,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ClipExporter {
+    pub fn start(params: ExportParams) -> Self {
+        let ExportParams {
+            src,
+            output,
+            start,
+            end,
+            mode,
+            video_filter,
+            on_progress,
+            on_done,
+        } = params;
+        Self::spawn(
+            output,
+            (start, end),
+            on_progress,
+            on_done,
+            move |output, range, progress| match mode {
+                ExportMode::Copy => copy_clip(src, output, range, progress),
+                ExportMode::Encode => {
+                    encode_clip(src, output, range, video_filter.as_deref(), progress)
+                }
+            },
+        )
+    }
+
+    /// Run `job` on a new thread, removing the output if it fails
+    pub(crate) fn spawn<F>(
+        output: String,
+        (start, end): (f32, f32),
+        mut on_progress: Box<dyn FnMut(f32) + Send + 'static>,
+        on_done: Box<dyn FnOnce(Result<String, anyhow::Error>) + Send + 'static>,
+        job: F,
+    ) -> Self
+    where
+        F: FnOnce(&str, (f64, f64), &mut Progress) -> Result<(), anyhow::Error> + Send + 'static,
+    {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let thread_cancelled = cancelled.clone();
+        let interrupter = Interrupter::default();
+        let thread_interrupter = interrupter.clone();
+        let thread = thread::spawn(move || {
+            let range = (start.max(0.0) as f64, end as f64);
+            let mut progress = Progress::new(
+                range,
+                &mut on_progress,
+                &thread_cancelled,
+                thread_interrupter,
+            );
+            let result = job(&output, range, &mut progress);
+            if result.is_err() {
+                let _ = std::fs::remove_file(&output);
+            }
+            if !thread_cancelled.load(Ordering::Relaxed) {
+                on_done(result.map(|_| output));
+            }
+        });
+        Self {
+            cancelled,
+            interrupter,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn cancel(&self) {
+        if !self.cancelled.swap(true, Ordering::Relaxed) {
+            // Never acknowledged, so every further IO of the export fails right away
+            self.interrupter.request();
+        }
+    }
+}
+
+impl Drop for ClipExporter {
+    fn drop(&mut self) {
+        self.cancel();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Reports the position within the range and checks for cancellation
+pub(crate) struct Progress<'a> {
+    range: (f64, f64),
+    reported: f32,
+    on_progress: &'a mut dyn FnMut(f32),
+    cancelled: &'a AtomicBool,
+    interrupter: Interrupter,
+}
+
+impl<'a> Progress<'a> {
+    fn new(
+        range: (f64, f64),
+        on_progress: &'a mut dyn FnMut(f32),
+        cancelled: &'a AtomicBool,
+        interrupter: Interrupter,
+    ) -> Self {
+        Self {
+            range,
+            reported: 0.0,
+            on_progress,
+            cancelled,
+            interrupter,
+        }
+    }
+
+    /// Interrupts the IO of the source once cancelled
+    pub(crate) fn interrupter(&self) -> Interrupter {
+        self.interrupter.clone()
+    }
+
+    pub(crate) fn update(&mut self, time: f64) -> Result<(), anyhow::Error> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(anyhow!("cancelled"));
+        }
+        let (start, end) = self.range;
+        let progress = ((time - start) / (end - start).max(0.001)).clamp(0.0, 1.0) as f32;
+        if progress - self.reported >= 0.01 {
+            self.reported = progress;
+            (self.on_progress)(progress);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(&mut self) {
+        (self.on_progress)(1.0);
+    }
+}
+
+/// Open the source and position it at the keyframe before `start`
+pub(crate) fn open_at(
+    src: MediaSource,
+    start: f64,
+    interrupter: Interrupter,
+) -> Result<SourceInput, anyhow::Error> {
+    let mut input = SourceInput::open(src, OpenOptions::default(), interrupter)?;
+    if !input.is_seekable() && start > 0.0 {
+        return Err(anyhow!("source is not seekable"));
+    }
+    seek_input(&mut input, start)?;
+    Ok(input)
+}
+
+pub(crate) fn seek_input(input: &mut SourceInput, time: f64) -> Result<(), anyhow::Error> {
+    if input.is_seekable() {
+        let ts = (time * AV_TIME_BASE as f64) as i64;
+        input.seek(ts, ..ts)?;
+    }
+    Ok(())
+}
+
+fn create_output(output: &str) -> Result<Output, anyhow::Error> {
+    format::output(&output).map_err(|e| anyhow!("cannot create {}: {}", output, e))
+}
+
+fn packet_time(packet: &Packet, time_base: Rational) -> Option<f64> {
+    packet
+        .pts()
+        .or(packet.dts())
+        .map(|ts| ts as f64 * f64::from(time_base))
+}
+
+fn copy_clip(
+    src: MediaSource,
+    output: &str,
+    (start, end): (f64, f64),
+    progress: &mut Progress,
+) -> Result<(), anyhow::Error> {
+    let mut input = open_at(src, start, progress.interrupter())?;
+    let mut octx = create_output(output)?;
+    let video_index = input
+        .streams()
+        .best(media::Type::Video)
+        .map(|s| s.index())
+        .ok_or(anyhow!("no video stream"))?;
+    let audio_index = input.streams().best(media::Type::Audio).map(|s| s.index());
+
+    // Output stream index for each input stream
+    let mut mapping = vec![None; input.streams().count()];
+    for index in [Some(video_index), audio_index].into_iter().flatten() {
+        let stream = input.stream(index).unwrap();
+        let mut ost = octx.add_stream(encoder::find(codec::Id::None))?;
+        ost.set_parameters(stream.parameters());
+        // Let the muxer pick a tag valid for the container
+        unsafe {
+            (*ost.parameters().as_mut_ptr()).codec_tag = 0;
+        }
+        mapping[index] = Some(ost.index());
+    }
+    octx.write_header()?;
+
+    // Timestamps are shifted so that the first keyframe starts at 0
+    let mut clip_origin: Option<f64> = None;
+    // Streams whose packets have passed the end, the others are still read
+    let mut finished: Vec<bool> = mapping.iter().map(Option::is_none).collect();
+    while let Some(mut packet) = input.read_packet()? {
+        let index = packet.stream();
+        let Some(out_index) = mapping.get(index).copied().flatten() else {
+            continue;
+        };
+        let time_base = input.stream(index).unwrap().time_base();
+        let Some(time) = packet_time(&packet, time_base) else {
+            continue;
+        };
+        let dts = packet.dts().map(|dts| dts as f64 * f64::from(time_base));
+        let origin = match clip_origin {
+            Some(origin) => origin,
+            // Cut on the keyframe at or before start, frames before it cannot be decoded
+            None if index == video_index && packet.is_key() => {
+                *clip_origin.insert(dts.unwrap_or(time).min(time))
+            }
+            None => continue,
+        };
+        if time < origin {
+            continue;
+        }
+        // Video is cut in decoding order: B-frames shown before the end are decoded after
+        // the frames they reference, which may be shown after it
+        let past_end = match dts {
+            Some(dts) if index == video_index => dts > end,
+            _ => time > end,
+        };
+        if past_end {
+            finished[index] = true;
+            if finished.iter().all(|&finished| finished) {
+                break;
+            }
+            continue;
+        }
+        let offset = (origin / f64::from(time_base)) as i64;
+        packet.set_pts(packet.pts().map(|pts| pts - offset));
+        packet.set_dts(packet.dts().map(|dts| dts - offset));
+        packet.rescale_ts(time_base, octx.stream(out_index).unwrap().time_base());
+        packet.set_stream(out_index);
+        packet.set_position(-1);
+        packet.write_interleaved(&mut octx)?;
+        progress.update(time)?;
+    }
+    if clip_origin.is_none() {
+        return Err(anyhow!("no keyframe in range"));
+    }
+    octx.write_trailer()?;
+    progress.finish();
+    Ok(())
+}
+
+/// Video encoding state of a re-encoded clip
+struct VideoTranscoder {
+    input_index: usize,
+    input_time_base: Rational,
+    output_index: usize,
+    decoder: decoder::Video,
+    filter: VideoFilter,
+    encoder: encoder::video::Encoder,
+    /// Output time base of the filter, finer than the input one when fields become frames
+    time_base: Rational,
+    /// Filtered pts of `start`, subtracted from the output timestamps
+    start_pts: i64,
+    done: bool,
+}
+
+/// Audio encoding state of a re-encoded clip
+struct AudioTranscoder {
+    input_index: usize,
+    input_time_base: Rational,
+    output_index: usize,
+    decoder: decoder::Audio,
+    /// Converts to the sample format and frame size of the encoder
+    filter: AudioFilter,
+    encoder: encoder::audio::Encoder,
+    time_base: Rational,
+    /// Samples passed to the filter so far, the timestamps of the output
+    samples: i64,
+    done: bool,
+}
+
+fn encode_clip(
+    src: MediaSource,
+    output: &str,
+    (start, end): (f64, f64),
+    video_filter: Option<&str>,
+    progress: &mut Progress,
+) -> Result<(), anyhow::Error> {
+    let mut input = open_at(src, start, progress.interrupter())?;
+    let mut octx = create_output(output)?;
+    let global_header = octx.format().flags().contains(format::Flags::GLOBAL_HEADER);
+
+    let mut video =
+        create_video_transcoder(&mut input, &mut octx, output, video_filter, global_header)?;
+    video.start_pts = (start / f64::from(video.time_base)) as i64;
+    let mut audio = match input.streams().best(media::Type::Audio).map(|s| s.index()) {
+        Some(index) => Some(create_audio_transcoder(
+            &input,
+            index,
+            &mut octx,
+            output,
+            global_header,
+        )?),
+        None => None,
+    };
+    // The video setup decoded a frame, start over
+    seek_input(&mut input, start)?;
+    video.decoder.flush();
+    octx.write_header()?;
+
+    // Audio may end after the video, keep reading until both are past the end
+    while !video.done || audio.as_ref().map_or(false, |audio| !audio.done) {
+        let Some(packet) = input.read_packet()? else {
+            break;
+        };
+        if packet.stream() == video.input_index {
+            if !video.done {
+                video.decoder.send_packet(&packet)?;
+                video.receive_frames(&mut octx, (start, end), progress)?;
+            }
+        } else if let Some(audio) = audio.as_mut().filter(|a| a.input_index == packet.stream()) {
+            if !audio.done {
+                audio.decoder.send_packet(&packet)?;
+                audio.receive_frames(&mut octx, (start, end))?;
+            }
+        }
+    }
+    // Drain the decoders, the filters and the encoders
+    video.decoder.send_eof()?;
+    video.receive_frames(&mut octx, (start, end), progress)?;
+    video.filter.flush();
+    video.write_filtered(&mut octx, end, progress)?;
+    video.encoder.send_eof()?;
+    write_packets(
+        &mut video.encoder,
+        video.time_base,
+        video.output_index,
+        &mut octx,
+    )?;
+    if let Some(audio) = &mut audio {
+        audio.decoder.send_eof()?;
+        audio.receive_frames(&mut octx, (start, end))?;
+        audio.filter.flush();
+        audio.write_filtered(&mut octx)?;
+        audio.encoder.send_eof()?;
+        write_packets(
+            &mut audio.encoder,
+            audio.time_base,
+            audio.output_index,
+            &mut octx,
+        )?;
+    }
+    octx.write_trailer()?;
+    progress.finish();
+    Ok(())
+}
+
+fn create_video_transcoder(
+    input: &mut SourceInput,
+    octx: &mut Output,
+    output: &str,
+    video_filter: Option<&str>,
+    global_header: bool,
+) -> Result<VideoTranscoder, anyhow::Error> {
+    let stream = input
+        .streams()
+        .best(media::Type::Video)
+        .ok_or(anyhow!("no video stream"))?;
+    let input_index = stream.index();
+    let input_time_base = stream.time_base();
+    let frame_rate = stream.avg_frame_rate();
+    let mut decoder = codec::Context::from_parameters(stream.parameters())?
+        .decoder()
+        .video()?;
+
+    let codec = encoder::find_by_name("libx264")
+        .or_else(|| encoder::find(octx.format().codec(&output, media::Type::Video)))
+        .ok_or(anyhow!("no video encoder available"))?;
+    let formats: Vec<Pixel> = codec
+        .video()?
+        .formats()
+        .map(|formats| formats.collect())
+        .unwrap_or_default();
+    let pixel_format = match formats.first() {
+        Some(&first) if !formats.contains(&Pixel::YUV420P) => first,
+        _ => Pixel::YUV420P,
+    };
+
+    // The filtered size is only known once the graph has seen a frame
+    let first_frame = input.decode_next_frame(&mut decoder, input_index)?;
+    let pixel_format_name = pixel_format
+        .descriptor()
+        .map(|d| d.name())
+        .unwrap_or("yuv420p");
+    // Most encoders require even dimensions
+    let conversion = format!(
+        "scale=trunc(iw/2)*2:trunc(ih/2)*2,format={}",
+        pixel_format_name
+    );
+    let spec = match video_filter {
+        Some(spec) => format!("{},{}", spec, conversion),
+        None => conversion,
+    };
+    let filter = VideoFilter::new(&spec, &first_frame, input_time_base)?;
+    let (format, width, height) = filter.output_format();
+    let time_base = filter.output_time_base();
+
+    let mut ost = octx.add_stream(codec)?;
+    let output_index = ost.index();
+    let mut context = codec::context::Context::new_with_codec(codec)
+        .encoder()
+        .video()?;
+    context.set_width(width);
+    context.set_height(height);
+    context.set_format(format);
+    context.set_time_base(time_base);
+    context.set_frame_rate(Some(frame_rate));
+    if global_header {
+        context.set_flags(codec::Flags::GLOBAL_HEADER);
+    }
+    let mut options = Dictionary::new();
+    options.set("preset", "veryfast");
+    let encoder = context.open_with(options)?;
+    ost.set_parameters(&encoder);
+    ost.set_time_base(time_base);
+    Ok(VideoTranscoder {
+        input_index,
+        input_time_base,
+        output_index,
+        decoder,
+        filter,
+        encoder,
+        time_base,
+        start_pts: 0,
+        done: false,
+    })
+}
+
+fn create_audio_transcoder(
+    input: &SourceInput,
+    input_index: usize,
+    octx: &mut Output,
+    output: &str,
+    global_header: bool,
+) -> Result<AudioTranscoder, anyhow::Error> {
+    let stream = input.stream(input_index).unwrap();
+    let input_time_base = stream.time_base();
+    let decoder = codec::Context::from_parameters(stream.parameters())?
+        .decoder()
+        .audio()?;
+    let codec = encoder::find(codec::Id::AAC)
+        .or_else(|| encoder::find(octx.format().codec(&output, media::Type::Audio)))
+        .ok_or(anyhow!("no audio encoder available"))?;
+    let input_format = AudioFormat::of_decoder(&decoder);
+    let sample_format = codec
+        .audio()?
+        .formats()
+        .and_then(|mut formats| formats.next())
+        .unwrap_or(input_format.format);
+    let time_base = Rational::new(1, input_format.rate as i32);
+
+    let mut ost = octx.add_stream(codec)?;
+    let output_index = ost.index();
+    let mut context = codec::context::Context::new_with_codec(codec)
+        .encoder()
+        .audio()?;
+    context.set_rate(input_format.rate as i32);
+    context.set_channel_layout(input_format.channel_layout);
+    context.set_format(sample_format);
+    context.set_time_base(time_base);
+    if global_header {
+        context.set_flags(codec::Flags::GLOBAL_HEADER);
+    }
+    let encoder = context.open_as(codec)?;
+    ost.set_parameters(&encoder);
+    ost.set_time_base(time_base);
+
+    let spec = format!(
+        "aformat=sample_fmts={}:sample_rates={}:channel_layouts=0x{:x}",
+        sample_format.name(),
+        input_format.rate,
+        input_format.channel_layout.bits(),
+    );
+    let mut filter = AudioFilter::new(&spec, input_format)?;
+    if encoder.frame_size() > 0 {
+        filter.set_frame_size(encoder.frame_size());
+    }
+    Ok(AudioTranscoder {
+        input_index,
+        input_time_base,
+        output_index,
+        decoder,
+        filter,
+        encoder,
+        time_base,
+        samples: 0,
+        done: false,
+    })
+}
+
+/// Mux the packets the encoder has ready
+pub(crate) fn write_packets(
+    encoder: &mut encoder::Encoder,
+    time_base: Rational,
+    output_index: usize,
+    octx: &mut Output,
+) -> Result<(), anyhow::Error> {
+    let output_time_base = octx.stream(output_index).unwrap().time_base();
+    let mut packet = Packet::empty();
+    while encoder.receive_packet(&mut packet).is_ok() {
+        packet.set_stream(output_index);
+        packet.rescale_ts(time_base, output_time_base);
+        packet.write_interleaved(octx)?;
+    }
+    Ok(())
+}
+
+impl VideoTranscoder {
+    fn receive_frames(
+        &mut self,
+        octx: &mut Output,
+        (start, end): (f64, f64),
+        progress: &mut Progress,
+    ) -> Result<(), anyhow::Error> {
+        let mut decoded = frame::Video::empty();
+        while self.decoder.receive_frame(&mut decoded).is_ok() {
+            let time = decoded.pts().unwrap_or(0) as f64 * f64::from(self.input_time_base);
+            // Decoding starts at the keyframe before start
+            if time < start {
+                continue;
+            }
+            if time > end {
+                self.done = true;
+                break;
+            }
+            self.filter.push(&decoded)?;
+            self.write_filtered(octx, end, progress)?;
+        }
+        Ok(())
+    }
+
+    fn write_filtered(
+        &mut self,
+        octx: &mut Output,
+        end: f64,
+        progress: &mut Progress,
+    ) -> Result<(), anyhow::Error> {
+        while let Some(mut filtered) = self.filter.pull() {
+            let pts = filtered.pts().unwrap_or(0);
+            let time = pts as f64 * f64::from(self.time_base);
+            if time > end {
+                continue;
+            }
+            filtered.set_pts(Some(pts - self.start_pts));
+            // Keep the source's frame types from forcing keyframes in the encoder
+            filtered.set_kind(picture::Type::None);
+            self.encoder.send_frame(&filtered)?;
+            write_packets(&mut self.encoder, self.time_base, self.output_index, octx)?;
+            progress.update(time)?;
+        }
+        Ok(())
+    }
+}
+
+impl AudioTranscoder {
+    fn receive_frames(
+        &mut self,
+        octx: &mut Output,
+        (start, end): (f64, f64),
+    ) -> Result<(), anyhow::Error> {
+        let mut decoded = frame::Audio::empty();
+        while self.decoder.receive_frame(&mut decoded).is_ok() {
+            let time = decoded.pts().unwrap_or(0) as f64 * f64::from(self.input_time_base);
+            if time < start {
+                continue;
+            }
+            if time > end {
+                self.done = true;
+                break;
+            }
+            decoded.set_pts(Some(self.samples));
+            self.samples += decoded.samples() as i64;
+            self.filter.push(&decoded)?;
+            self.write_filtered(octx)?;
+        }
+        Ok(())
+    }
+
+    fn write_filtered(&mut self, octx: &mut Output) -> Result<(), anyhow::Error> {
+        while let Some(filtered) = self.filter.pull() {
+            self.encoder.send_frame(&filtered)?;
+            write_packets(&mut self.encoder, self.time_base, self.output_index, octx)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_media::{decode_video, temp_dir, TestServer, TestVideo};
+    use std::path::Path;
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    fn export(src: &Path, output: &Path, (start, end): (f32, f32), mode: ExportMode) {
+        let (sender, receiver) = mpsc::channel();
+        let _exporter = ClipExporter::start(ExportParams {
+            src: MediaSource::Url(src.to_str().unwrap().to_string()),
+            output: output.to_str().unwrap().to_string(),
+            start,
+            end,
+            mode,
+            video_filter: None,
+            on_progress: Box::new(|_| {}),
+            on_done: Box::new(move |result| sender.send(result).unwrap()),
+        });
+        let result = receiver.recv_timeout(Duration::from_secs(30)).unwrap();
+        assert_eq!(result.unwrap(), output.to_str().unwrap());
+    }
+
+    #[test]
+    fn copy_keeps_b_frames_shown_before_end() {
+        let dir = temp_dir("export-copy");
+        let path = dir.join("video.mkv");
+        TestVideo {
+            frames: 100,
+            b_frames: 2,
+            ..TestVideo::default()
+        }
+        .write(&path, None, &[]);
+        let output = dir.join("clip.mkv");
+        export(&path, &output, (1.0, 2.0), ExportMode::Copy);
+
+        // Starts at the keyframe at 1s, frame 25
+        let frames = decode_video(&output);
+        let indices: Vec<usize> = frames
+            .iter()
+            .map(|(_, frame)| TestVideo::frame_index(frame, 20..60))
+            .collect();
+        assert_eq!(indices[0], 25, "{:?}", indices);
+        for index in 25..=50 {
+            assert!(indices.contains(&index), "frame {} missing: {:?}", index, indices);
+        }
+        let first = frames[0].0;
+        assert!(frames.windows(2).all(|pair| pair[1].0 > pair[0].0));
+        assert!(frames.iter().all(|(time, _)| time - first < 1.2));
+    }
+
+    #[test]
+    fn encode_starts_and_ends_at_the_range() {
+        let dir = temp_dir("export-encode");
+        let path = dir.join("video.mkv");
+        TestVideo {
+            frames: 100,
+            ..TestVideo::default()
+        }
+        .write(&path, None, &[]);
+        let output = dir.join("clip.mkv");
+        // Between frames, 1.16 and 1.2 for the start, 2.0 and 2.04 for the end
+        export(&path, &output, (1.18, 2.02), ExportMode::Encode);
+
+        let frames = decode_video(&output);
+        let indices: Vec<usize> = frames
+            .iter()
+            .map(|(_, frame)| TestVideo::frame_index(frame, 29..57))
+            .collect();
+        assert_eq!(indices, (30..=50).collect::<Vec<_>>());
+        assert!(frames[0].0 < 0.05, "clip starts at {}", frames[0].0);
+    }
+
+    #[test]
+    fn cancel_interrupts_stalled_read() {
+        let dir = temp_dir("export-cancel");
+        let path = dir.join("video.mkv");
+        TestVideo {
+            frames: 500,
+            ..TestVideo::default()
+        }
+        .write(&path, None, &[]);
+        let server = TestServer::start(&dir);
+        let size = std::fs::metadata(&path).unwrap().len() as usize;
+        server.stall_after.store(size / 2, Ordering::SeqCst);
+
+        let (progress_sender, progress) = mpsc::channel();
+        let (done_sender, done) = mpsc::channel();
+        let output = dir.join("clip.mkv");
+        let exporter = ClipExporter::start(ExportParams {
+            src: MediaSource::Url(server.url("video.mkv")),
+            output: output.to_str().unwrap().to_string(),
+            start: 0.0,
+            end: 20.0,
+            mode: ExportMode::Copy,
+            video_filter: None,
+            on_progress: Box::new(move |value| {
+                let _ = progress_sender.send(value);
+            }),
+            on_done: Box::new(move |result| done_sender.send(result.is_ok()).unwrap()),
+        });
+        progress.recv_timeout(Duration::from_secs(10)).unwrap();
+        // Let the export run into the stall
+        std::thread::sleep(Duration::from_millis(300));
+
+        let cancelled = Instant::now();
+        drop(exporter);
+        assert!(cancelled.elapsed() < Duration::from_secs(2), "{:?}", cancelled.elapsed());
+        assert!(done.try_recv().is_err());
+        assert!(!output.exists());
+    }
+}
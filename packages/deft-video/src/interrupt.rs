@@ -0,0 +1,139 @@
+use std::ffi::{c_int, c_void};
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A read blocking longer than this is reported as a stall
+const STALL_THRESHOLD: Duration = Duration::from_millis(500);
+
+/// Blocking IO was aborted because a control request is waiting
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl Display for Interrupted {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Aborts blocking IO of the inputs it is attached to, so that e.g. Stop does not hang on a
+/// stalled connection. Also reports reads that block for long (buffering).
+#[derive(Clone)]
+pub struct Interrupter {
+    state: Arc<InterruptState>,
+}
+
+struct InterruptState {
+    /// Requests sent to the player thread but not received yet
+    pending: AtomicUsize,
+    deadline: Mutex<Option<Instant>>,
+    /// Start of the read in progress
+    read_started: Mutex<Option<Instant>>,
+    stalled: AtomicBool,
+    /// Called with true when a read stalls and false when it completes afterwards
+    on_stall: Mutex<Box<dyn FnMut(bool) + Send + 'static>>,
+}
+
+impl Default for Interrupter {
+    fn default() -> Self {
+        Self::new(Box::new(|_| {}))
+    }
+}
+
+impl Interrupter {
+    pub fn new(on_stall: Box<dyn FnMut(bool) + Send + 'static>) -> Self {
+        let state = InterruptState {
+            pending: AtomicUsize::new(0),
+            deadline: Mutex::new(None),
+            read_started: Mutex::new(None),
+            stalled: AtomicBool::new(false),
+            on_stall: Mutex::new(on_stall),
+        };
+        Self {
+            state: Arc::new(state),
+        }
+    }
+
+    /// Abort blocking IO until the request is acknowledged, call before sending it
+    pub fn request(&self) {
+        self.state.pending.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Called by the player thread once it has received a request
+    pub fn acknowledge(&self) {
+        let _ = self
+            .state
+            .pending
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+    }
+
+    pub fn has_pending(&self) -> bool {
+        self.state.pending.load(Ordering::SeqCst) > 0
+    }
+
+    /// Poll like ffmpeg does while blocked, for waits outside of ffmpeg
+    pub fn is_interrupted(&self) -> bool {
+        self.state.is_interrupted()
+    }
+
+    /// Run `f`, aborting its IO once `timeout` has elapsed
+    pub fn with_timeout<T>(&self, timeout: Option<Duration>, f: impl FnOnce() -> T) -> T {
+        *self.state.deadline.lock().unwrap() = timeout.map(|t| Instant::now() + t);
+        let result = f();
+        *self.state.deadline.lock().unwrap() = None;
+        result
+    }
+
+    /// Run a read, reporting a stall if it blocks for long
+    pub fn monitor_read<T>(&self, f: impl FnOnce() -> T) -> T {
+        *self.state.read_started.lock().unwrap() = Some(Instant::now());
+        let result = f();
+        *self.state.read_started.lock().unwrap() = None;
+        if self.state.stalled.swap(false, Ordering::SeqCst) {
+            (self.state.on_stall.lock().unwrap())(false);
+        }
+        result
+    }
+
+    /// Convert an error of an interrupted call into `Interrupted` or a timeout
+    pub fn map_error(&self, error: ffmpeg_next::Error) -> anyhow::Error {
+        match error {
+            ffmpeg_next::Error::Exit if self.has_pending() => Interrupted.into(),
+            ffmpeg_next::Error::Exit => anyhow::anyhow!("timed out"),
+            e => e.into(),
+        }
+    }
+
+    /// Opaque pointer for `interrupt_callback`, valid as long as this interrupter is alive
+    pub fn as_opaque(&self) -> *mut c_void {
+        Arc::as_ptr(&self.state) as *mut c_void
+    }
+}
+
+impl InterruptState {
+    fn is_interrupted(&self) -> bool {
+        if self.pending.load(Ordering::SeqCst) > 0 {
+            return true;
+        }
+        let now = Instant::now();
+        if matches!(*self.deadline.lock().unwrap(), Some(deadline) if now >= deadline) {
+            return true;
+        }
+        let read_started = *self.read_started.lock().unwrap();
+        if let Some(started) = read_started {
+            if now - started >= STALL_THRESHOLD && !self.stalled.swap(true, Ordering::SeqCst) {
+                (self.on_stall.lock().unwrap())(true);
+            }
+        }
+        false
+    }
+}
+
+/// Polled by ffmpeg while blocked in IO, non-zero aborts the call with AVERROR_EXIT
+pub unsafe extern "C" fn interrupt_callback(opaque: *mut c_void) -> c_int {
+    let state = &*(opaque as *const InterruptState);
+    state.is_interrupted() as c_int
+}
@@ -0,0 +1,312 @@
+use cpal::Sample;
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+use serde::Serialize;
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Samples analysed for the spectrum, about 43ms at 48kHz
+const SPECTRUM_SIZE: usize = 2048;
+/// Samples buffered between the output callback and the meter thread, about 0.7s of stereo
+/// at 48kHz
+const TAP_CAPACITY: usize = 65536;
+/// How often the meter thread picks up the played samples
+const METER_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Lowest frequency of the spectrum bands
+const SPECTRUM_MIN_FREQUENCY: f32 = 20.0;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct AudioLevels {
+    /// Peak amplitude per channel since the previous report, 0-1
+    pub peak: Vec<f32>,
+    /// RMS amplitude per channel since the previous report, 0-1
+    pub rms: Vec<f32>,
+    /// Amplitude of logarithmically spaced bands from 20Hz up, empty unless requested
+    pub spectrum: Vec<f32>,
+}
+
+/// How often levels are reported, changed while playing
+#[derive(Clone, Default)]
+pub struct MeterSettings(Arc<(AtomicU32, AtomicU32)>);
+
+impl MeterSettings {
+    /// Report every `interval_ms`, 0 to stop metering, with `bands` spectrum bands, 0 for none
+    pub fn set(&self, interval_ms: u32, bands: u32) {
+        self.0 .0.store(interval_ms, Ordering::Relaxed);
+        self.0 .1.store(bands, Ordering::Relaxed);
+    }
+
+    fn interval_ms(&self) -> u32 {
+        self.0 .0.load(Ordering::Relaxed)
+    }
+
+    fn bands(&self) -> usize {
+        self.0 .1.load(Ordering::Relaxed) as usize
+    }
+}
+
+/// Settings and receiver of the level reports, shared with every audio output of a player
+#[derive(Clone)]
+pub struct AudioMeter {
+    pub settings: MeterSettings,
+    pub on_levels: Arc<Mutex<Box<dyn FnMut(AudioLevels) + Send + 'static>>>,
+}
+
+/// Copies the samples handed to the output device for the meter thread. Runs in the output
+/// callback, so it neither locks nor allocates
+pub struct MeterTap {
+    settings: MeterSettings,
+    producer: HeapProducer<f32>,
+}
+
+impl MeterTap {
+    pub fn push<T: Sample>(&mut self, data: &[T]) {
+        if self.settings.interval_ms() == 0 {
+            return;
+        }
+        // Whole buffers only so that the meter keeps the channels apart, the meter thread
+        // lagging behind loses samples rather than blocking the output
+        if self.producer.free_len() < data.len() {
+            return;
+        }
+        self.producer
+            .push_iter(&mut data.iter().map(|s| s.to_float_sample().to_sample::<f32>()));
+    }
+}
+
+/// Thread measuring the samples of a `MeterTap` and reporting the levels, stopped on drop
+pub struct MeterThread {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MeterThread {
+    pub fn start(meter: AudioMeter, channels: usize, rate: u32) -> (MeterTap, Self) {
+        let (producer, consumer) = HeapRb::new(TAP_CAPACITY).split();
+        let tap = MeterTap {
+            settings: meter.settings.clone(),
+            producer,
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        let level_meter = LevelMeter::new(meter, channels, rate);
+        let handle = {
+            let stop = stop.clone();
+            thread::spawn(move || run_meter(level_meter, consumer, &stop))
+        };
+        let thread = Self {
+            stop,
+            handle: Some(handle),
+        };
+        (tap, thread)
+    }
+}
+
+impl Drop for MeterThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run_meter(mut level_meter: LevelMeter, mut consumer: HeapConsumer<f32>, stop: &AtomicBool) {
+    let mut samples = vec![0.0; TAP_CAPACITY];
+    while !stop.load(Ordering::Acquire) {
+        let count = consumer.pop_slice(&mut samples);
+        level_meter.process(&samples[..count]);
+        if count < samples.len() {
+            thread::sleep(METER_POLL_INTERVAL);
+        }
+    }
+}
+
+/// Measures the samples handed to the output device, so that reports follow what is heard
+/// rather than what is queued
+struct LevelMeter {
+    meter: AudioMeter,
+    channels: usize,
+    rate: u32,
+    peak: Vec<f32>,
+    sum_squares: Vec<f32>,
+    frames: usize,
+    /// Most recent samples mixed to mono, a ring buffer
+    history: Vec<f32>,
+    history_pos: usize,
+}
+
+impl LevelMeter {
+    fn new(meter: AudioMeter, channels: usize, rate: u32) -> Self {
+        Self {
+            meter,
+            channels: channels.max(1),
+            rate,
+            peak: vec![0.0; channels.max(1)],
+            sum_squares: vec![0.0; channels.max(1)],
+            frames: 0,
+            history: vec![0.0; SPECTRUM_SIZE],
+            history_pos: 0,
+        }
+    }
+
+    /// Account for interleaved samples about to be played, reporting once an interval is full
+    fn process(&mut self, data: &[f32]) {
+        let interval_ms = self.meter.settings.interval_ms();
+        if interval_ms == 0 {
+            self.frames = 0;
+            return;
+        }
+        for frame in data.chunks_exact(self.channels) {
+            let mut mono = 0.0;
+            for (channel, &value) in frame.iter().enumerate() {
+                self.peak[channel] = self.peak[channel].max(value.abs());
+                self.sum_squares[channel] += value * value;
+                mono += value;
+            }
+            self.history[self.history_pos] = mono / self.channels as f32;
+            self.history_pos = (self.history_pos + 1) % SPECTRUM_SIZE;
+            self.frames += 1;
+        }
+        if self.frames as u64 * 1000 >= self.rate as u64 * interval_ms as u64 {
+            let levels = self.take_levels();
+            (self.meter.on_levels.lock().unwrap())(levels);
+        }
+    }
+
+    fn take_levels(&mut self) -> AudioLevels {
+        let frames = self.frames.max(1) as f32;
+        let levels = AudioLevels {
+            peak: self.peak.clone(),
+            rms: self
+                .sum_squares
+                .iter()
+                .map(|sum| (sum / frames).sqrt())
+                .collect(),
+            spectrum: self.spectrum(self.meter.settings.bands()),
+        };
+        self.peak.fill(0.0);
+        self.sum_squares.fill(0.0);
+        self.frames = 0;
+        levels
+    }
+
+    fn spectrum(&self, bands: usize) -> Vec<f32> {
+        if bands == 0 {
+            return Vec::new();
+        }
+        let mut re = vec![0.0; SPECTRUM_SIZE];
+        let mut im = vec![0.0; SPECTRUM_SIZE];
+        for (i, value) in re.iter_mut().enumerate() {
+            let sample = self.history[(self.history_pos + i) % SPECTRUM_SIZE];
+            // Hann window
+            let window = 0.5 - 0.5 * (2.0 * PI * i as f32 / SPECTRUM_SIZE as f32).cos();
+            *value = sample * window;
+        }
+        fft(&mut re, &mut im);
+        // Scaled so that a full scale sine reads 1, the window halves the amplitude
+        let scale = 4.0 / SPECTRUM_SIZE as f32;
+        let bins = SPECTRUM_SIZE / 2;
+        let bin_width = self.rate as f32 / SPECTRUM_SIZE as f32;
+        let max_frequency = self.rate as f32 / 2.0;
+        let ratio = max_frequency / SPECTRUM_MIN_FREQUENCY;
+        (0..bands)
+            .map(|band| {
+                let low = SPECTRUM_MIN_FREQUENCY * ratio.powf(band as f32 / bands as f32);
+                let high = SPECTRUM_MIN_FREQUENCY * ratio.powf((band + 1) as f32 / bands as f32);
+                let start = ((low / bin_width) as usize).min(bins - 1);
+                // Narrow low bands may fall between bins, use the nearest one
+                let end = ((high / bin_width).ceil() as usize).clamp(start + 1, bins);
+                (start..end)
+                    .map(|k| (re[k] * re[k] + im[k] * im[k]).sqrt() * scale)
+                    .fold(0.0, f32::max)
+            })
+            .collect()
+    }
+}
+
+/// In-place radix-2 FFT, the length must be a power of two
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let a = start + k;
+                let b = a + len / 2;
+                let tr = re[b] * cos - im[b] * sin;
+                let ti = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn meter_thread_reports_tapped_samples() {
+        let (sender, receiver) = mpsc::channel();
+        let meter = AudioMeter {
+            settings: MeterSettings::default(),
+            on_levels: Arc::new(Mutex::new(Box::new(move |levels| {
+                let _ = sender.send(levels);
+            }))),
+        };
+        meter.settings.set(20, 0);
+        let (mut tap, thread) = MeterThread::start(meter, 2, 48000);
+        // 20ms of stereo, left at half scale and right silent
+        let buffer: Vec<f32> = (0..960).flat_map(|_| [0.5, 0.0]).collect();
+        tap.push(&buffer);
+        let levels = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(levels.peak, vec![0.5, 0.0]);
+        assert!((levels.rms[0] - 0.5).abs() < 1e-4);
+        assert_eq!(levels.rms[1], 0.0);
+        assert!(levels.spectrum.is_empty());
+        drop(thread);
+    }
+
+    #[test]
+    fn tap_drops_buffers_while_metering_is_off() {
+        let meter = AudioMeter {
+            settings: MeterSettings::default(),
+            on_levels: Arc::new(Mutex::new(Box::new(|_| {}))),
+        };
+        let (producer, consumer) = HeapRb::<f32>::new(16).split();
+        let mut tap = MeterTap {
+            settings: meter.settings.clone(),
+            producer,
+        };
+        tap.push(&[1.0f32; 4]);
+        assert!(consumer.is_empty());
+        meter.settings.set(100, 0);
+        tap.push(&[1.0f32; 4]);
+        assert_eq!(consumer.len(), 4);
+        // A buffer that does not fit is dropped whole
+        tap.push(&[1.0f32; 16]);
+        assert_eq!(consumer.len(), 4);
+    }
+}
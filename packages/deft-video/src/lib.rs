@@ -1,14 +1,37 @@
-use crate::video::VideoBackend;
+pub use crate::probe::{probe, ChapterInfo, ProbeInfo, StreamInfo};
+pub use crate::source::{MediaSource, ReadSeek};
+pub use crate::video::VideoBackend;
 use deft::element::register_component;
 use deft::js::js_engine::JsEngine;
 
+mod animation;
+mod audio_delay;
+mod audio_filter;
+mod byte_stream;
+mod export;
+mod interrupt;
+mod levels;
+mod live;
+mod object_fit;
 mod player;
 mod player_thread;
+mod probe;
+mod scaling;
+mod snapshot;
+mod source;
+mod state;
+mod subtitles;
+#[cfg(test)]
+mod test_media;
+mod thumbnails;
+mod variants;
 mod video;
+mod video_filter;
 
 pub fn deft_video_init(js_engine: &mut JsEngine) {
     register_component::<VideoBackend>("video");
     js_engine.add_global_functions(VideoBackend::create_js_apis());
+    js_engine.add_global_func(probe::video_probe::new());
     js_engine
         .eval_module(include_str!("video.js"), "video.js")
         .unwrap();
@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+/// Delay between receiving and presenting a live frame, absorbs network jitter
+pub const LIVE_LATENCY: Duration = Duration::from_millis(200);
+/// Live frames later than this are dropped to catch up
+pub const LIVE_DROP_THRESHOLD: Duration = Duration::from_millis(100);
+/// Beyond this drift (a stall or a timestamp jump) the clock is re-anchored instead
+pub const LIVE_MAX_DRIFT: Duration = Duration::from_secs(1);
+/// First delay before reconnecting, doubled after every failed attempt
+pub const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+pub const MAX_RECONNECT_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LiveMode {
+    /// Live for streaming protocols and HLS playlists without an end
+    Auto,
+    On,
+    Off,
+}
+
+impl LiveMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        let mode = match value.trim().to_ascii_lowercase().as_str() {
+            "auto" => Self::Auto,
+            "on" => Self::On,
+            "off" => Self::Off,
+            _ => return None,
+        };
+        Some(mode)
+    }
+}
+
+/// Whether the URL uses a protocol that only carries live streams
+pub fn is_live_url(url: &str) -> bool {
+    let Some((scheme, _)) = url.split_once("://") else {
+        return false;
+    };
+    matches!(
+        scheme.to_ascii_lowercase().as_str(),
+        "rtsp" | "rtsps" | "rtmp" | "rtmps" | "srt" | "udp" | "rtp" | "tcp"
+    )
+}
+
+/// Whether `Auto` treats an opened source as live. Files, buffers and pushed streams may lack
+/// a duration too, so only live protocols and live HLS playlists count
+pub fn is_live_source(live_url: bool, format: &str, duration: i64) -> bool {
+    live_url || (format == "hls" && duration <= 0)
+}
+
+/// Delay before reconnect attempt `attempt`, counted from 0
+pub fn reconnect_delay(attempt: u32) -> Duration {
+    RECONNECT_INTERVAL
+        .saturating_mul(1 << attempt.min(16))
+        .min(MAX_RECONNECT_INTERVAL)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LiveFrameAction {
+    Present,
+    /// Late, skip it to catch up
+    Drop,
+    /// Restart the jitter buffer at this frame, after a stall or a timestamp jump
+    Resync,
+}
+
+/// What to do with a live frame that is `lateness` seconds behind its presentation time
+pub fn live_frame_action(lateness: f64) -> LiveFrameAction {
+    if lateness.abs() > LIVE_MAX_DRIFT.as_secs_f64() {
+        LiveFrameAction::Resync
+    } else if lateness > LIVE_DROP_THRESHOLD.as_secs_f64() {
+        LiveFrameAction::Drop
+    } else {
+        LiveFrameAction::Present
+    }
+}
+
+/// Format options that keep the demuxer from buffering ahead
+pub fn low_latency_options() -> [(&'static str, &'static str); 3] {
+    [
+        ("fflags", "nobuffer"),
+        ("probesize", "500000"),
+        ("analyzeduration", "1000000"),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_detects_live_protocols_and_playlists() {
+        assert!(is_live_source(true, "rtsp", 0));
+        assert!(is_live_source(true, "flv", 0));
+        assert!(is_live_source(false, "hls", 0));
+        // VOD playlists and files without a duration, e.g. pushed fMP4 or webm
+        assert!(!is_live_source(false, "hls", 60_000_000));
+        assert!(!is_live_source(false, "matroska,webm", 0));
+        assert!(!is_live_source(false, "mov,mp4,m4a,3gp,3g2,mj2", -1));
+    }
+
+    #[test]
+    fn live_urls() {
+        assert!(is_live_url("rtsp://camera/stream"));
+        assert!(is_live_url("RTMP://server/app"));
+        assert!(is_live_url("udp://239.0.0.1:1234"));
+        assert!(is_live_url("srt://server:9000"));
+        assert!(!is_live_url("https://server/video.m3u8"));
+        assert!(!is_live_url("/home/user/video.mp4"));
+    }
+
+    #[test]
+    fn reconnect_backs_off() {
+        let delays: Vec<u64> = (0..7).map(|i| reconnect_delay(i).as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 30, 30]);
+        assert_eq!(reconnect_delay(u32::MAX), MAX_RECONNECT_INTERVAL);
+    }
+
+    #[test]
+    fn drift_handling() {
+        assert_eq!(live_frame_action(-0.5), LiveFrameAction::Present);
+        assert_eq!(live_frame_action(0.05), LiveFrameAction::Present);
+        assert_eq!(live_frame_action(0.5), LiveFrameAction::Drop);
+        assert_eq!(live_frame_action(1.5), LiveFrameAction::Resync);
+        // Far ahead of the clock, e.g. after a timestamp jump
+        assert_eq!(live_frame_action(-1.5), LiveFrameAction::Resync);
+    }
+}
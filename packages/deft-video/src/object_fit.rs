@@ -0,0 +1,253 @@
+use skia_safe::Rect;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObjectFit {
+    Contain,
+    Cover,
+    Fill,
+    None,
+    ScaleDown,
+}
+
+impl ObjectFit {
+    pub fn parse(value: &str) -> Option<Self> {
+        let fit = match value.trim().to_ascii_lowercase().as_str() {
+            "contain" => Self::Contain,
+            "cover" => Self::Cover,
+            "fill" => Self::Fill,
+            "none" => Self::None,
+            "scale-down" => Self::ScaleDown,
+            _ => return None,
+        };
+        Some(fit)
+    }
+}
+
+/// Alignment of the image inside the view, as fractions of the free space (0.5 = centered)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ObjectPosition {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Default for ObjectPosition {
+    fn default() -> Self {
+        Self { x: 0.5, y: 0.5 }
+    }
+}
+
+impl ObjectPosition {
+    /// Parse values like `center`, `left top`, `right 20%` or `30% 70%`
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim().to_ascii_lowercase();
+        let parts: Vec<&str> = value.split_whitespace().collect();
+        let mut position = Self::default();
+        match parts.as_slice() {
+            [v] => match component_axis(v)? {
+                Axis::Vertical => position.y = parse_component(v)?,
+                _ => position.x = parse_component(v)?,
+            },
+            [a, b] => {
+                let (a_axis, b_axis) = (component_axis(a)?, component_axis(b)?);
+                // Keywords may be given in either order, e.g. `top left`, percentages may not
+                let swap = a_axis == Axis::Vertical || b_axis == Axis::Horizontal;
+                let (x, x_axis, y, y_axis) = if swap {
+                    (b, b_axis, a, a_axis)
+                } else {
+                    (a, a_axis, b, b_axis)
+                };
+                let keywords = is_keyword(x) && is_keyword(y);
+                if x_axis == Axis::Vertical || y_axis == Axis::Horizontal || (swap && !keywords) {
+                    return None;
+                }
+                position.x = parse_component(x)?;
+                position.y = parse_component(y)?;
+            }
+            _ => return None,
+        }
+        Some(position)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Axis {
+    Horizontal,
+    Vertical,
+    /// `center` and percentages
+    Either,
+}
+
+fn component_axis(value: &str) -> Option<Axis> {
+    let axis = match value {
+        "left" | "right" => Axis::Horizontal,
+        "top" | "bottom" => Axis::Vertical,
+        "center" => Axis::Either,
+        _ if value.ends_with('%') => Axis::Either,
+        _ => return None,
+    };
+    Some(axis)
+}
+
+fn is_keyword(value: &str) -> bool {
+    !value.ends_with('%')
+}
+
+fn parse_component(value: &str) -> Option<f32> {
+    let v = match value {
+        "left" | "top" => 0.0,
+        "center" => 0.5,
+        "right" | "bottom" => 1.0,
+        _ => value.strip_suffix('%')?.parse::<f32>().ok()? / 100.0,
+    };
+    Some(v)
+}
+
+/// Compute the destination rect of an image inside a view. The result may exceed the view
+/// bounds (`cover`, `none`), so callers should clip to the view.
+pub fn compute_dest_rect(
+    view_size: (f32, f32),
+    image_size: (f32, f32),
+    fit: ObjectFit,
+    position: ObjectPosition,
+) -> Rect {
+    let (view_width, view_height) = view_size;
+    let (image_width, image_height) = image_size;
+    if image_width <= 0.0 || image_height <= 0.0 {
+        return Rect::new_empty();
+    }
+    let contain_scale = f32::min(view_width / image_width, view_height / image_height);
+    let (width, height) = match fit {
+        ObjectFit::Fill => (view_width, view_height),
+        ObjectFit::Contain => (image_width * contain_scale, image_height * contain_scale),
+        ObjectFit::Cover => {
+            let scale = f32::max(view_width / image_width, view_height / image_height);
+            (image_width * scale, image_height * scale)
+        }
+        ObjectFit::None => (image_width, image_height),
+        ObjectFit::ScaleDown => {
+            let scale = f32::min(contain_scale, 1.0);
+            (image_width * scale, image_height * scale)
+        }
+    };
+    let left = (view_width - width) * position.x;
+    let top = (view_height - height) * position.y;
+    Rect::from_xywh(left, top, width, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIEW: (f32, f32) = (400.0, 300.0);
+
+    fn rect(fit: ObjectFit, position: &str, image_size: (f32, f32)) -> (f32, f32, f32, f32) {
+        let position = ObjectPosition::parse(position).unwrap();
+        let r = compute_dest_rect(VIEW, image_size, fit, position);
+        (r.left, r.top, r.width(), r.height())
+    }
+
+    #[test]
+    fn fill_stretches_to_view() {
+        assert_eq!(
+            rect(ObjectFit::Fill, "center", (1920.0, 1080.0)),
+            (0.0, 0.0, 400.0, 300.0)
+        );
+    }
+
+    #[test]
+    fn contain_letterboxes() {
+        assert_eq!(
+            rect(ObjectFit::Contain, "center", (800.0, 400.0)),
+            (0.0, 50.0, 400.0, 200.0)
+        );
+        assert_eq!(
+            rect(ObjectFit::Contain, "top", (800.0, 400.0)),
+            (0.0, 0.0, 400.0, 200.0)
+        );
+        assert_eq!(
+            rect(ObjectFit::Contain, "left 100%", (800.0, 400.0)),
+            (0.0, 100.0, 400.0, 200.0)
+        );
+    }
+
+    #[test]
+    fn cover_overflows_view() {
+        assert_eq!(
+            rect(ObjectFit::Cover, "center", (800.0, 400.0)),
+            (-100.0, 0.0, 600.0, 300.0)
+        );
+        assert_eq!(
+            rect(ObjectFit::Cover, "right", (800.0, 400.0)),
+            (-200.0, 0.0, 600.0, 300.0)
+        );
+        assert_eq!(
+            rect(ObjectFit::Cover, "25% 50%", (800.0, 400.0)),
+            (-50.0, 0.0, 600.0, 300.0)
+        );
+    }
+
+    #[test]
+    fn none_keeps_image_size() {
+        assert_eq!(
+            rect(ObjectFit::None, "center", (200.0, 100.0)),
+            (100.0, 100.0, 200.0, 100.0)
+        );
+        assert_eq!(
+            rect(ObjectFit::None, "bottom right", (200.0, 100.0)),
+            (200.0, 200.0, 200.0, 100.0)
+        );
+    }
+
+    #[test]
+    fn scale_down_only_shrinks() {
+        assert_eq!(
+            rect(ObjectFit::ScaleDown, "center", (200.0, 100.0)),
+            (100.0, 100.0, 200.0, 100.0)
+        );
+        assert_eq!(
+            rect(ObjectFit::ScaleDown, "left top", (800.0, 400.0)),
+            (0.0, 0.0, 400.0, 200.0)
+        );
+    }
+
+    #[test]
+    fn empty_image_gives_empty_rect() {
+        assert!(compute_dest_rect(
+            VIEW,
+            (0.0, 100.0),
+            ObjectFit::Contain,
+            ObjectPosition::default()
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn parse_position() {
+        let parse = |v| ObjectPosition::parse(v).map(|p| (p.x, p.y));
+        assert_eq!(parse("center"), Some((0.5, 0.5)));
+        assert_eq!(parse("left"), Some((0.0, 0.5)));
+        assert_eq!(parse("Bottom"), Some((0.5, 1.0)));
+        assert_eq!(parse("top left"), Some((0.0, 0.0)));
+        assert_eq!(parse("LEFT Top"), Some((0.0, 0.0)));
+        assert_eq!(parse("center right"), Some((1.0, 0.5)));
+        assert_eq!(parse("right 20%"), Some((1.0, 0.2)));
+        assert_eq!(parse("30% 70%"), Some((0.3, 0.7)));
+    }
+
+    #[test]
+    fn parse_rejects_invalid_positions() {
+        for value in [
+            "",
+            "left left",
+            "top bottom",
+            "right left",
+            "bottom top",
+            "20% left",
+            "top 20%",
+            "middle",
+            "10% 20% 30%",
+        ] {
+            assert_eq!(ObjectPosition::parse(value), None, "{}", value);
+        }
+    }
+}
@@ -1,96 +1,472 @@
+use crate::audio_delay::AudioDelay;
+use crate::audio_filter::{AudioFilter, AudioFormat};
+use crate::scaling::{create_rescale_context, need_rebuild, ScaleSettings};
+use crate::snapshot::{save_frame, CaptureRequest};
+use crate::interrupt::{Interrupted, Interrupter};
+use crate::levels::{AudioMeter, MeterThread};
+use crate::live::{live_frame_action, reconnect_delay, LiveFrameAction, LIVE_LATENCY};
+use crate::source::{MediaSource, OpenOptions, SourceInput};
+use crate::state::SharedVolume;
+use crate::subtitles::active_cue;
+use crate::variants::{enable_variants, list_variants, variant_streams, AbrController, Variant};
+use crate::video_filter::{
+    filter_chain, DeinterlaceMode, FilterFailed, FilterKind, VideoFilter, VideoFormat,
+};
 use anyhow::anyhow;
 use bytemuck::Pod;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{SizedSample};
+use cpal::{Sample, SizedSample};
 use ffmpeg_next::decoder::{Audio, Video};
 use ffmpeg_next::ffi::{av_rescale_rnd, swr_get_delay, AV_TIME_BASE};
-use ffmpeg_next::format::context::Input;
-use ffmpeg_next::format::Pixel;
 use ffmpeg_next::software::resampling::Context;
 use ffmpeg_next::threading::Config;
 use ffmpeg_next::{frame, threading, Rational};
 use ringbuf::{HeapRb, Producer, SharedRb};
 use serde::Serialize;
+use std::collections::VecDeque;
 use std::mem::MaybeUninit;
-use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{RecvTimeoutError, TryRecvError};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
+use std::thread::JoinHandle;
 use std::time::Duration;
 use ffmpeg_next::ffi::AVRounding::AV_ROUND_UP;
 
+#[derive(Serialize, Clone, Debug)]
+pub struct FrameStep {
+    pub pts: i64,
+    pub time: f32,
+    /// Frame index derived from the average frame rate
+    pub frame: i64,
+}
+
 #[derive(Serialize, Clone, Debug)]
 pub struct Meta {
     pub width: usize,
     pub height: usize,
     pub duration: f32,
+    /// False for forward-only sources, seeking requests are rejected
+    pub seekable: bool,
+    /// Live streams have no duration and cannot be seeked
+    pub live: bool,
+    /// Renditions of an adaptive source, empty for other sources
+    pub variants: Vec<Variant>,
 }
 
 pub struct PlayServer {
     video_stream_index: usize,
     packet_decoder: Video,
 
-    audio_stream_index: usize,
-    audio_packet_decoder: Audio,
-    input_context: Input,
+    /// None for sources without audio, e.g. most camera feeds
+    audio_stream_index: Option<usize>,
+    audio_packet_decoder: Option<Audio>,
+    input_context: SourceInput,
+    interrupter: Interrupter,
+    /// Copy of the source to reconnect to when a live stream is lost, None unless remote
+    reconnect_source: Option<MediaSource>,
+    open_options: OpenOptions,
+    live: bool,
+    variants: Vec<Variant>,
+    current_variant: Option<usize>,
+    /// Select variants from the measured throughput
+    auto_variant: bool,
+    /// Variant being switched to, takes over at its next keyframe
+    pending_switch: Option<VariantSwitch>,
+    abr: AbrController,
     timebase: Rational,
+    frame_rate: Rational,
     rescale_context: Option<ffmpeg_next::software::scaling::Context>,
-    audio_sender: Option<mpsc::Sender<frame::Audio>>,
+    rescale_settings: ScaleSettings,
+    scale_settings: Arc<Mutex<ScaleSettings>>,
+    /// Size of the frames before scaling, changes when filters crop or rotate
+    source_size: Arc<Mutex<Option<(f32, f32)>>>,
+    filter_options: FilterOptions,
+    /// An interlaced frame has been decoded, enables automatic deinterlacing
+    interlaced: bool,
+    /// Built for the current frame format on the next decoded frame
+    video_filter: Option<VideoFilter>,
+    volume: SharedVolume,
+    /// Milliseconds the audio is shifted against the video
+    audio_delay: i32,
+    audio_sender: Option<mpsc::Sender<AudioMessage>>,
+    audio_thread: Option<JoinHandle<()>>,
+    /// Incremented on every seek, audio queued before it is discarded
+    audio_generation: Arc<AtomicUsize>,
     latest_frame: Option<frame::Video>,
+    /// Time base of the pts of `latest_frame`, the filter's output time base once filtered
+    latest_time_base: Rational,
     stream_clock: Option<StreamClock>,
+    /// EOF has been sent to the decoders, remaining frames are being drained
+    eof_sent: bool,
+    /// All frames have been drained after EOF
+    eof_reached: bool,
+    /// Decoded frame to return before decoding further, set when stepping backward
+    queued_frame: Option<(frame::Video, Rational)>,
+    /// Reported to `on_error` by the play loop
+    filter_error: Option<FilterFailed>,
+    /// Audio graphs the audio thread removed, reported to `on_error` by the play loop
+    audio_filter_errors: mpsc::Receiver<FilterFailed>,
+    audio_filter_error_sender: mpsc::Sender<FilterFailed>,
+    /// Frames the previous variant's decoder held when the switch was committed
+    drained_frames: VecDeque<(frame::Video, Rational)>,
+    /// Seconds up to which packets have been demuxed since the last seek
+    demuxed_until: Option<f64>,
+    /// The audio output was closed for a variant with another audio format
+    reopen_audio: bool,
+}
+
+struct VariantSwitch {
+    id: usize,
+    video_stream_index: usize,
+    audio_stream_index: Option<usize>,
+    decoder: Video,
+    /// Set when the variant carries its own audio stream
+    audio_decoder: Option<Audio>,
+}
+
+/// Completion callback of a control message
+pub type Reply = Box<dyn FnOnce(Result<(), anyhow::Error>) + Send + 'static>;
+
+pub struct LoadRequest {
+    pub source: MediaSource,
+    pub options: OpenOptions,
+    pub preview_time: Option<f32>,
+    pub filters: FilterOptions,
+    /// Replies when metadata has been loaded
+    pub reply: Option<Reply>,
+}
+
+/// Processing of the decoded frames, kept by the element across sources
+#[derive(Clone, Debug)]
+pub struct FilterOptions {
+    /// libavfilter description applied to the video
+    pub video: Option<String>,
+    pub deinterlace: DeinterlaceMode,
+    /// libavfilter description applied to the audio before resampling
+    pub audio: Option<String>,
+}
+
+impl Default for FilterOptions {
+    fn default() -> Self {
+        Self {
+            video: None,
+            deinterlace: DeinterlaceMode::Auto,
+            audio: None,
+        }
+    }
+}
+
+pub struct PlayCallbacks {
+    /// Presents a frame, the flag is set for the preview shown after loading
+    pub renderer: Box<dyn FnMut(frame::Video, bool) + Send + 'static>,
+    pub on_progress: Box<dyn FnMut(f32) + Send + 'static>,
+    /// Time up to which a network source has been downloaded
+    pub on_buffered: Box<dyn FnMut(f32) + Send + 'static>,
+    pub on_stop: Box<dyn FnMut() + Send + 'static>,
+    pub on_ended: Box<dyn FnMut() + Send + 'static>,
+    /// Called when the player has accepted a play, pause or seek request
+    pub on_play: Box<dyn FnMut() + Send + 'static>,
+    pub on_pause: Box<dyn FnMut() + Send + 'static>,
+    pub on_seeking: Box<dyn FnMut() + Send + 'static>,
+    /// Failures to open or play a source, and filters removed because they failed
+    pub on_error: Box<dyn FnMut(anyhow::Error) + Send + 'static>,
+    /// Reports the levels of the audio being played
+    pub audio_meter: AudioMeter,
+}
+
+/// Why `PlayServer::play` returned
+pub enum PlayExit {
+    Stopped,
+    /// Reading or decoding failed, the source cannot be played further
+    Failed(anyhow::Error),
+    Load(LoadRequest),
+    /// The control channel was closed
+    Disconnected,
 }
 
 pub enum ControlMessage {
-    Play,
+    /// Replace the source, reusing the player thread
+    Load(LoadRequest),
+    /// Replies when the first frame after resuming has been presented
+    Play(Option<Reply>),
     Pause,
-    Seek(f32),
+    /// Replies when the frame at the target time has been presented
+    Seek(f32, Option<Reply>),
     Stop,
+    Capture(CaptureRequest),
+    /// Show the next (+1) or previous (-1) frame
+    StepFrame(i32, Box<dyn FnOnce(Result<FrameStep, anyhow::Error>) + Send + 'static>),
+    SetLoop(bool),
+    /// Repeat the range between start and end, None to clear
+    SetLoopRange(Option<(f32, f32)>),
+    /// Play the given variant of an adaptive source, None to select automatically
+    SelectVariant(Option<usize>),
+    /// Replace the video filter graph, None to remove it. Fails if the description is invalid
+    SetVideoFilter(Option<String>, Reply),
+    SetDeinterlace(DeinterlaceMode),
+    /// Replace the audio filter graph, None to remove it. Fails if the description is invalid
+    SetAudioFilter(Option<String>, Reply),
+    /// Milliseconds to play the audio after (positive) or before (negative) the video
+    SetAudioDelay(i32),
+}
+
+impl ControlMessage {
+    /// Whether the message should abort blocking IO of the player thread
+    pub fn interrupts(&self) -> bool {
+        matches!(
+            self,
+            ControlMessage::Load(_) | ControlMessage::Seek(..) | ControlMessage::Stop
+        )
+    }
 }
 
 impl PlayServer {
-    pub fn new(path: String) -> Self {
-        let input_context = ffmpeg_next::format::input(&path).unwrap();
-        let video_stream = input_context
-            .streams()
-            .best(ffmpeg_next::media::Type::Video)
-            .unwrap();
+    pub fn new(
+        source: MediaSource,
+        options: OpenOptions,
+        interrupter: Interrupter,
+        filter_options: FilterOptions,
+        scale_settings: Arc<Mutex<ScaleSettings>>,
+        source_size: Arc<Mutex<Option<(f32, f32)>>>,
+        volume: SharedVolume,
+    ) -> Result<Self, anyhow::Error> {
+        let reconnect_source = source.try_clone();
+        let mut input_context = SourceInput::open(source, options, interrupter.clone())?;
+        // The end of a file or buffer is the end of playback, even when played as live
+        let reconnect_source = reconnect_source.filter(|_| input_context.is_remote());
+        let live = input_context.is_live();
+        let variants = list_variants(&input_context);
+        // Start with the lightest variant, the automatic selection moves up once measured
+        let current_variant = variants.iter().min_by_key(|v| v.bandwidth).map(|v| v.id);
+        let (video_stream_index, audio_stream_index) =
+            select_streams(&mut input_context, current_variant);
+        let video_stream = video_stream_index
+            .and_then(|index| input_context.stream(index))
+            .ok_or(anyhow!("no video stream"))?;
         let video_stream_index = video_stream.index();
+        let timebase = video_stream.time_base();
+        let frame_rate = video_stream.avg_frame_rate();
+        let packet_decoder = create_video_decoder(&video_stream, live)?;
 
-        let mut decoder_context =
-            ffmpeg_next::codec::Context::from_parameters(video_stream.parameters()).unwrap();
-        let mut threading_config = Config::default();
-        threading_config.count = num_cpus::get();
-        threading_config.kind = threading::Type::Frame;
-        decoder_context.set_threading(threading_config);
-        let packet_decoder = decoder_context.decoder().video().unwrap();
-
-        let audio_stream = input_context
-            .streams()
-            .best(ffmpeg_next::media::Type::Audio)
-            .unwrap();
-        let audio_stream_index = audio_stream.index();
-        let audio_decoder_context =
-            ffmpeg_next::codec::Context::from_parameters(audio_stream.parameters()).unwrap();
-        let audio_packet_decoder = audio_decoder_context.decoder().audio().unwrap();
+        let audio_packet_decoder = match audio_stream_index.and_then(|i| input_context.stream(i)) {
+            Some(audio_stream) => Some(create_audio_decoder(&audio_stream)?),
+            None => None,
+        };
+        let (audio_filter_error_sender, audio_filter_errors) = mpsc::channel();
         let mut player = Self {
             video_stream_index,
             packet_decoder,
             audio_stream_index,
             audio_packet_decoder,
-            timebase: video_stream.time_base(),
+            timebase,
+            frame_rate,
             input_context,
+            interrupter,
+            reconnect_source,
+            open_options: options,
+            live,
+            variants,
+            current_variant,
+            auto_variant: true,
+            pending_switch: None,
+            abr: AbrController::default(),
             audio_sender: None,
+            audio_thread: None,
+            audio_generation: Arc::new(AtomicUsize::new(0)),
             rescale_context: None,
+            rescale_settings: ScaleSettings::default(),
+            scale_settings,
+            source_size,
+            filter_options,
+            interlaced: false,
+            video_filter: None,
+            volume,
+            audio_delay: 0,
             latest_frame: None,
+            latest_time_base: timebase,
             stream_clock: None,
+            eof_sent: false,
+            eof_reached: false,
+            queued_frame: None,
+            filter_error: None,
+            audio_filter_errors,
+            audio_filter_error_sender,
+            drained_frames: VecDeque::new(),
+            demuxed_until: None,
+            reopen_audio: false,
         };
-        let _ = player.next_frame(false);
-        player
+        player.next_frame(false)?;
+        Ok(player)
     }
 
     pub fn get_duration(&self) -> f32 {
-        let duration = self.input_context.duration();
+        if self.live {
+            return 0.0;
+        }
+        let duration = self.input_context.duration().max(0);
         duration as f32 / ffmpeg_next::ffi::AV_TIME_BASE as f32
     }
 
+    pub fn is_seekable(&self) -> bool {
+        self.input_context.is_seekable()
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.live
+    }
+
+    pub fn get_variants(&self) -> Vec<Variant> {
+        self.variants.clone()
+    }
+
+    fn select_variant(&mut self, choice: Option<usize>) {
+        self.auto_variant = choice.is_none();
+        if let Some(id) = choice {
+            if let Err(e) = self.begin_variant_switch(id) {
+                println!("failed to select variant {}: {:?}", id, e);
+            }
+        }
+    }
+
+    /// Start downloading variant `id` alongside the current one, it takes over at its next
+    /// keyframe so that playback does not stall
+    fn begin_variant_switch(&mut self, id: usize) -> Result<(), anyhow::Error> {
+        if self.current_variant == Some(id) {
+            self.pending_switch = None;
+            enable_variants(&mut self.input_context, &[id]);
+            return Ok(());
+        }
+        let (video_stream_index, audio_stream_index) = variant_streams(&self.input_context, id);
+        let video_stream_index = video_stream_index.ok_or(anyhow!("variant has no video stream"))?;
+        let video_stream = self.input_context.stream(video_stream_index).unwrap();
+        let decoder = create_video_decoder(&video_stream, self.live)?;
+        // Renditions may differ in codec, sample rate or channels, decode them separately
+        let audio_decoder = match audio_stream_index {
+            Some(index) if Some(index) != self.audio_stream_index => {
+                let audio_stream = self.input_context.stream(index).unwrap();
+                Some(create_audio_decoder(&audio_stream)?)
+            }
+            _ => None,
+        };
+        let mut enabled = vec![id];
+        enabled.extend(self.current_variant);
+        enable_variants(&mut self.input_context, &enabled);
+        self.pending_switch = Some(VariantSwitch {
+            id,
+            video_stream_index,
+            audio_stream_index,
+            decoder,
+            audio_decoder,
+        });
+        Ok(())
+    }
+
+    fn update_auto_variant(&mut self) {
+        if !self.auto_variant || self.pending_switch.is_some() {
+            return;
+        }
+        let Some(current) = self.current_variant else {
+            return;
+        };
+        if let Some(id) = self.abr.choose(&self.variants, current) {
+            if let Err(e) = self.begin_variant_switch(id) {
+                println!("failed to switch to variant {}: {:?}", id, e);
+            }
+        }
+    }
+
+    /// Hand over to the pending variant if `packet` is its first keyframe
+    fn commit_variant_switch(&mut self, packet: &ffmpeg_next::Packet) {
+        let Some(switch) = &self.pending_switch else {
+            return;
+        };
+        if packet.stream() != switch.video_stream_index || !packet.is_key() {
+            return;
+        }
+        let switch = self.pending_switch.take().unwrap();
+        let Some(stream) = self.input_context.stream(switch.video_stream_index) else {
+            return;
+        };
+        let (time_base, frame_rate) = (stream.time_base(), stream.avg_frame_rate());
+        let switch_time = packet
+            .pts()
+            .or(packet.dts())
+            .map(|pts| pts as f64 * f64::from(time_base));
+        // Frame threading holds back several frames, present them before the new variant
+        self.drain_video_decoder(switch_time);
+        self.packet_decoder = switch.decoder;
+        self.video_stream_index = switch.video_stream_index;
+        self.timebase = time_base;
+        self.frame_rate = frame_rate;
+        if let Some(audio_decoder) = switch.audio_decoder {
+            self.switch_audio_decoder(audio_decoder);
+        }
+        if switch.audio_stream_index.is_some() {
+            self.audio_stream_index = switch.audio_stream_index;
+        }
+        self.current_variant = Some(switch.id);
+        enable_variants(&mut self.input_context, &[switch.id]);
+    }
+
+    /// Decode the audio of a new variant. The output is reopened by the play loop when the
+    /// format differs, the samples already queued are played out first
+    fn switch_audio_decoder(&mut self, audio_decoder: Audio) {
+        let format_of = |decoder: &Audio| {
+            (decoder.format(), decoder.rate(), decoder.channel_layout())
+        };
+        let format_changed = match &self.audio_packet_decoder {
+            Some(previous) => format_of(previous) != format_of(&audio_decoder),
+            None => true,
+        };
+        self.audio_packet_decoder = Some(audio_decoder);
+        if format_changed && self.audio_sender.is_some() {
+            self.close_audio(true);
+            self.reopen_audio = true;
+        }
+    }
+
+    /// Decode the rest of the current stream into `drained_frames`, up to `until` seconds
+    fn drain_video_decoder(&mut self, until: Option<f64>) {
+        if self.packet_decoder.send_eof().is_err() {
+            return;
+        }
+        while let Some((frame, time_base)) = self.receive_video_frame() {
+            let time = frame.pts().map(|pts| pts as f64 * f64::from(time_base));
+            if let (Some(time), Some(until)) = (time, until) {
+                if time >= until {
+                    continue;
+                }
+            }
+            self.drained_frames.push_back((frame, time_base));
+        }
+        // The graph has been flushed, the new variant gets its own
+        self.video_filter = None;
+    }
+
+    /// Time up to which the source has been downloaded, None for local sources. Taken from
+    /// the demuxed packets, byte positions mean nothing for playlists like HLS
+    pub fn get_buffered(&self) -> Option<f32> {
+        if !self.input_context.is_remote() || self.live {
+            return None;
+        }
+        let end = self.demuxed_until? as f32;
+        let duration = self.get_duration();
+        Some(if duration > 0.0 { end.min(duration) } else { end })
+    }
+
+    /// Extend the demuxed range with the end of `packet`
+    fn record_demuxed(&mut self, packet: &ffmpeg_next::Packet) {
+        let Some(stream) = self.input_context.stream(packet.stream()) else {
+            return;
+        };
+        let Some(start) = packet.pts().or(packet.dts()) else {
+            return;
+        };
+        let end = (start + packet.duration().max(0)) as f64 * f64::from(stream.time_base());
+        self.demuxed_until = Some(self.demuxed_until.map_or(end, |until| until.max(end)));
+    }
+
     pub fn get_width(&self) -> Option<usize> {
         self.latest_frame
             .as_ref()
@@ -105,88 +481,351 @@ impl PlayServer {
 
     pub fn play(
         &mut self,
-        mut renderer: Box<dyn FnMut(frame::Video) + Send + 'static>,
-        mut progress_handler: Box<dyn FnMut(f32)>,
-        control_msg_receiver: mpsc::Receiver<ControlMessage>,
-        mut stop_handler: Box<dyn FnMut()>,
-    ) {
-        let (audio_frame_sender, audio_frame_receiver) = mpsc::channel();
-        self.audio_sender = Some(audio_frame_sender);
-        let audio_playback =
-            AudioPlayback::<f32>::new(&self.audio_packet_decoder, audio_frame_receiver);
-
-        thread::spawn(move || {
-            // Note: playback will stop when audio_frame_sender dropped
-            audio_playback.run();
-        });
+        callbacks: &mut PlayCallbacks,
+        control_msg_receiver: &mpsc::Receiver<ControlMessage>,
+    ) -> PlayExit {
+        let PlayCallbacks {
+            renderer,
+            on_progress: progress_handler,
+            on_buffered: buffered_handler,
+            on_ended,
+            on_play,
+            on_pause,
+            on_seeking,
+            on_error,
+            audio_meter,
+            ..
+        } = callbacks;
+        self.start_audio(audio_meter);
 
+        let mut exit = None;
         let mut seek_time = None;
         let mut playing = false;
+        let mut looping = false;
+        let mut loop_range: Option<(f32, f32)> = None;
+        let mut play_reply: Option<Reply> = None;
+        let mut seek_reply: Option<Reply> = None;
+        let mut buffered = self.get_buffered();
+        // Received while waiting for a frame's presentation time
+        let mut pending_msgs = VecDeque::new();
+        // Seconds of the last frame handed to the clock
+        let mut presented_time: Option<f64> = None;
         loop {
-            let msg = if playing {
-                control_msg_receiver.try_recv().ok()
+            let msg = if let Some(msg) = pending_msgs.pop_front() {
+                Some(msg)
+            } else if playing {
+                match control_msg_receiver.try_recv() {
+                    Ok(msg) => Some(msg),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => {
+                        exit = Some(PlayExit::Disconnected);
+                        break;
+                    }
+                }
             } else {
-                control_msg_receiver.recv().ok()
+                match control_msg_receiver.recv() {
+                    Ok(msg) => Some(msg),
+                    Err(_) => {
+                        exit = Some(PlayExit::Disconnected);
+                        break;
+                    }
+                }
             };
 
             if let Some(msg) = msg {
+                if msg.interrupts() {
+                    self.interrupter.acknowledge();
+                }
                 match msg {
-                    ControlMessage::Play => {
+                    ControlMessage::Play(reply) => {
+                        if self.eof_reached && seek_time.is_none() && self.is_seekable() {
+                            // Replay from the start after the end
+                            seek_time = Some(0.0);
+                        }
+                        self.start_audio(audio_meter);
                         playing = true;
+                        replace_reply(&mut play_reply, reply);
+                        on_play();
                     }
                     ControlMessage::Pause => {
+                        if playing {
+                            on_pause();
+                        }
                         playing = false;
                         self.stream_clock = None;
                         continue;
                     }
-                    ControlMessage::Seek(time) => {
-                        self.seek(time);
+                    ControlMessage::Seek(_, Some(reply)) if !self.is_seekable() => {
+                        reply(Err(anyhow!("source is not seekable")));
+                        continue;
+                    }
+                    ControlMessage::Seek(_, None) if !self.is_seekable() => continue,
+                    ControlMessage::Seek(time, reply) => {
                         seek_time = Some(time);
+                        replace_reply(&mut seek_reply, reply);
+                        on_seeking();
                     }
                     ControlMessage::Stop => {
+                        exit = Some(PlayExit::Stopped);
                         break;
                     }
+                    ControlMessage::Load(request) => {
+                        exit = Some(PlayExit::Load(request));
+                        break;
+                    }
+                    ControlMessage::Capture(request) => {
+                        self.capture_frame(request);
+                        continue;
+                    }
+                    ControlMessage::StepFrame(delta, on_done) => {
+                        playing = false;
+                        self.stream_clock = None;
+                        let result = self.step_frame(delta);
+                        let result = result.map(|(rgb_frame, step)| {
+                            renderer(rgb_frame, false);
+                            progress_handler(step.time);
+                            step
+                        });
+                        on_done(result);
+                        continue;
+                    }
+                    ControlMessage::SetLoop(value) => {
+                        looping = value;
+                        continue;
+                    }
+                    ControlMessage::SetLoopRange(range) => {
+                        loop_range = range;
+                        continue;
+                    }
+                    ControlMessage::SelectVariant(choice) => {
+                        self.select_variant(choice);
+                        continue;
+                    }
+                    ControlMessage::SetDeinterlace(mode) => {
+                        self.filter_options.deinterlace = mode;
+                        continue;
+                    }
+                    ControlMessage::SetAudioDelay(delay) => {
+                        self.audio_delay = delay;
+                        if let Some(audio_sender) = &self.audio_sender {
+                            let _ = audio_sender.send(AudioMessage::SetDelay(delay));
+                        }
+                        continue;
+                    }
+                    ControlMessage::SetAudioFilter(spec, reply) => {
+                        reply(self.set_audio_filter(spec));
+                        continue;
+                    }
+                    ControlMessage::SetVideoFilter(spec, reply) => {
+                        let result = self.set_video_filter(spec);
+                        let changed = result.is_ok();
+                        reply(result);
+                        if changed && !playing {
+                            // Show the paused frame with the new filter applied
+                            if let Some(rgb_frame) = self.redecode_current_frame() {
+                                renderer(rgb_frame, false);
+                            }
+                        }
+                        continue;
+                    }
                 }
             }
 
-            if let Some(st) = seek_time {
-                if let Some(sc) = &self.stream_clock {
-                    //TODO impl fast accurate seek
-                    let mut pts = 0;
-                    let expected_ts = sc.convert_time_to_pts(st as f64);
-                    while pts < expected_ts {
-                        self.next_frame(false).unwrap();
-                        pts = self.latest_frame.as_ref().unwrap().pts().unwrap_or(0);
-                    }
+            let next_frame = match seek_time.take() {
+                Some(time) => {
                     self.stream_clock = None;
+                    self.seek_accurate(time)
                 }
-                seek_time = None;
+                None => self.next_frame(true),
+            };
+            if let Some(error) = self.filter_error.take() {
+                on_error(error.into());
             }
-
-            let rgb_frame = match self.next_frame(true) {
+            while let Ok(error) = self.audio_filter_errors.try_recv() {
+                // Unless another description has been set meanwhile
+                if self.filter_options.audio.as_deref() == Some(error.spec.as_str()) {
+                    self.filter_options.audio = None;
+                }
+                on_error(error.into());
+            }
+            let rgb_frame = match next_frame {
                 Ok(frame) => frame,
-                Err(_err) => break,
+                Err(_err)
+                    if self.eof_reached
+                        && (looping || loop_range.is_some())
+                        && self.is_seekable() =>
+                {
+                    let start = loop_range.map(|(start, _)| start).unwrap_or(0.0);
+                    seek_time = Some(start);
+                    continue;
+                }
+                Err(_err) if self.eof_reached && !self.reconnects() => {
+                    // Keep the source open so that play and seek work after the end
+                    self.close_audio(true);
+                    playing = false;
+                    self.stream_clock = None;
+                    if let Some(reply) = seek_reply.take() {
+                        reply(Ok(()));
+                    }
+                    if let Some(reply) = play_reply.take() {
+                        reply(Ok(()));
+                    }
+                    on_ended();
+                    continue;
+                }
+                // A control message is waiting, handle it before reading again
+                Err(err) if err.is::<Interrupted>() => continue,
+                Err(err) if self.reconnects() => {
+                    println!("live stream lost: {:?}", err);
+                    // Reported as waiting until the stream is back
+                    let interrupter = self.interrupter.clone();
+                    if let Err(err) = interrupter.monitor_read(|| self.reconnect()) {
+                        if !err.is::<Interrupted>() {
+                            exit = Some(PlayExit::Failed(err));
+                            break;
+                        }
+                    }
+                    continue;
+                }
+                // E.g. a network error or read timeout, reported rather than treated as a stop
+                Err(err) => {
+                    if let Some(reply) = seek_reply.take() {
+                        reply(Err(anyhow!("{}", err)));
+                    }
+                    exit = Some(PlayExit::Failed(err));
+                    break;
+                }
             };
+            if std::mem::take(&mut self.reopen_audio) {
+                self.start_audio(audio_meter);
+            }
             let pts = self.latest_frame.as_ref().unwrap().pts();
             // println!("pts: {:?}", pts);
+            let frame_time = pts.map(|pts| pts as f64 * f64::from(self.latest_time_base));
+            if let (Some(time), Some(presented)) = (frame_time, presented_time) {
+                // E.g. a new variant starting at a keyframe before the presented frame
+                if time < presented {
+                    self.stream_clock = None;
+                }
+            }
+            presented_time = frame_time.or(presented_time);
+            // Filtered frames and a new variant may come in another time base
+            if let Some(clock) = &self.stream_clock {
+                if clock.time_base != self.latest_time_base {
+                    self.stream_clock = None;
+                }
+            }
             if self.stream_clock.is_none() {
                 let latest_pts = self
                     .latest_frame
                     .as_ref()
                     .map(|f| f.pts().unwrap_or(0))
                     .unwrap_or(0);
-                self.stream_clock = Some(StreamClock::new(self.timebase, latest_pts));
+                let delay = if self.live { LIVE_LATENCY } else { Duration::ZERO };
+                self.stream_clock =
+                    Some(StreamClock::new(self.latest_time_base, latest_pts, delay));
+            }
+            if self.live {
+                let lateness = self.stream_clock.as_ref().unwrap().lateness(pts.unwrap_or(0));
+                match live_frame_action(lateness) {
+                    LiveFrameAction::Present => {}
+                    LiveFrameAction::Drop => continue,
+                    LiveFrameAction::Resync => {
+                        self.stream_clock = Some(StreamClock::new(
+                            self.latest_time_base,
+                            pts.unwrap_or(0),
+                            LIVE_LATENCY,
+                        ));
+                    }
+                }
             }
             let stream_clock = self.stream_clock.as_ref().unwrap();
             if let Some(delay) = stream_clock.convert_pts_to_instant(pts) {
                 //println!("delay: {:?}", delay);
-                thread::sleep(delay);
+                // Stop, load and seek discard the frame instead of waiting for it
+                if wait_for_message(control_msg_receiver, delay, &mut pending_msgs) {
+                    continue;
+                }
             }
-            renderer(rgb_frame);
+            renderer(rgb_frame, false);
             let time = stream_clock.convert_pts_to_time(pts.unwrap_or(0)) as f32;
             progress_handler(time);
+            self.update_auto_variant();
+            if let Some(end) = self.get_buffered() {
+                if buffered.map_or(true, |last| (end - last).abs() >= 0.5) {
+                    buffered = Some(end);
+                    buffered_handler(end);
+                }
+            }
+            if let Some(reply) = seek_reply.take() {
+                reply(Ok(()));
+            }
+            if playing {
+                if let Some(reply) = play_reply.take() {
+                    reply(Ok(()));
+                }
+            }
+            if let Some((start, end)) = loop_range {
+                if time >= end && self.is_seekable() {
+                    // Seek back in place, a JS round trip would leave an audible gap
+                    seek_time = Some(start);
+                }
+            }
+        }
+        for reply in [play_reply, seek_reply].into_iter().flatten() {
+            reply(Err(anyhow!("playback stopped")));
+        }
+        let exit = exit.unwrap_or(PlayExit::Stopped);
+        match exit {
+            PlayExit::Stopped => {
+                self.close_audio(false);
+                (callbacks.on_stop)();
+            }
+            PlayExit::Failed(_) | PlayExit::Load(_) | PlayExit::Disconnected => {
+                self.close_audio(false)
+            }
+        }
+        exit
+    }
+
+    /// Open the audio output if the source has audio and it is not open yet
+    fn start_audio(&mut self, meter: &AudioMeter) {
+        let Some(audio_packet_decoder) = &self.audio_packet_decoder else {
+            return;
+        };
+        if self.audio_sender.is_some() {
+            return;
+        }
+        let (audio_frame_sender, audio_frame_receiver) = mpsc::channel();
+        self.audio_sender = Some(audio_frame_sender);
+        let audio_playback = AudioPlayback::<f32>::new(
+            audio_packet_decoder,
+            audio_frame_receiver,
+            self.volume.clone(),
+            AudioSettings {
+                filter: self.filter_options.audio.clone(),
+                delay: self.audio_delay,
+            },
+            meter.clone(),
+            self.audio_generation.clone(),
+            self.audio_filter_error_sender.clone(),
+        );
+        self.audio_thread = Some(thread::spawn(move || {
+            // Note: playback will stop when audio_frame_sender dropped
+            audio_playback.run();
+        }));
+    }
+
+    /// Close the audio output, optionally waiting until the queued samples have been played
+    fn close_audio(&mut self, play_out: bool) {
+        if let Some(audio_sender) = self.audio_sender.take() {
+            if play_out {
+                let _ = audio_sender.send(AudioMessage::Finish);
+            }
+        }
+        if let Some(audio_thread) = self.audio_thread.take() {
+            let _ = audio_thread.join();
         }
-        stop_handler();
     }
 
     pub fn seek(&mut self, ts: f32) {
@@ -197,84 +836,488 @@ impl PlayServer {
         }
         // Clear buffers
         self.packet_decoder.flush();
-        self.audio_packet_decoder.flush();
+        if let Some(audio_packet_decoder) = &mut self.audio_packet_decoder {
+            audio_packet_decoder.flush();
+        }
+        self.eof_sent = false;
+        self.eof_reached = false;
+        self.queued_frame = None;
+        self.drained_frames.clear();
+        self.demuxed_until = None;
+        // Frames held back by the filters belong to the old position
+        self.video_filter = None;
+        // Audio past the old position must not be heard, e.g. past B when an A-B loop jumps
+        self.audio_generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn set_video_filter(&mut self, spec: Option<String>) -> Result<(), anyhow::Error> {
+        if let Some(spec) = &spec {
+            // Build once now so that an invalid description is reported to the caller. The
+            // decoder's format, the latest frame may have been through the previous filter
+            let format = VideoFormat::of_decoder(&self.packet_decoder);
+            VideoFilter::with_format(spec, format, self.timebase)?;
+        }
+        self.filter_options.video = spec;
+        self.video_filter = None;
+        Ok(())
+    }
+
+    fn set_audio_filter(&mut self, spec: Option<String>) -> Result<(), anyhow::Error> {
+        if let (Some(spec), Some(decoder)) = (&spec, &self.audio_packet_decoder) {
+            // Build once now so that an invalid description is reported to the caller
+            AudioFilter::new(spec, AudioFormat::of_decoder(decoder))?;
+        }
+        self.filter_options.audio = spec.clone();
+        if let Some(audio_sender) = &self.audio_sender {
+            let _ = audio_sender.send(AudioMessage::SetFilter(spec));
+        }
+        Ok(())
+    }
+
+    /// Decode the current frame again, e.g. after the filters have changed while paused
+    fn redecode_current_frame(&mut self) -> Option<frame::Video> {
+        if !self.is_seekable() {
+            return None;
+        }
+        let pts = self.latest_frame.as_ref()?.pts()?;
+        let time = pts as f64 * f64::from(self.latest_time_base);
+        self.seek_accurate(time as f32).ok()
+    }
+
+    /// Whether losing the source is followed by reconnecting rather than ending playback
+    fn reconnects(&self) -> bool {
+        self.live && self.reconnect_source.is_some()
+    }
+
+    /// Reopen a lost live stream, retrying until it is back or a control message is waiting
+    fn reconnect(&mut self) -> Result<(), anyhow::Error> {
+        let Some(source) = &self.reconnect_source else {
+            return Err(anyhow!("source cannot be reopened"));
+        };
+        let mut attempt = 0;
+        loop {
+            // Sleep in small steps to stay responsive to control messages
+            let retry_at = std::time::Instant::now() + reconnect_delay(attempt);
+            attempt = attempt.saturating_add(1);
+            while std::time::Instant::now() < retry_at {
+                if self.interrupter.is_interrupted() {
+                    return Err(Interrupted.into());
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+            let source = source.try_clone().unwrap();
+            let mut input = match SourceInput::open(source, self.open_options, self.interrupter.clone())
+            {
+                Ok(input) => input,
+                Err(e) if e.is::<Interrupted>() => return Err(e),
+                Err(e) => {
+                    println!("reconnect failed: {:?}", e);
+                    continue;
+                }
+            };
+            let (Some(video_stream_index), audio_stream_index) =
+                select_streams(&mut input, self.current_variant)
+            else {
+                continue;
+            };
+            let Some(video_stream) = input.stream(video_stream_index) else {
+                continue;
+            };
+            self.timebase = video_stream.time_base();
+            self.video_stream_index = video_stream_index;
+            self.audio_stream_index = audio_stream_index;
+            self.input_context = input;
+            self.pending_switch = None;
+            self.packet_decoder.flush();
+            if let Some(audio_packet_decoder) = &mut self.audio_packet_decoder {
+                audio_packet_decoder.flush();
+            }
+            self.eof_sent = false;
+            self.eof_reached = false;
+            self.stream_clock = None;
+            self.video_filter = None;
+            self.drained_frames.clear();
+            return Ok(());
+        }
+    }
+
+    /// Save the currently displayed frame at source resolution
+    pub fn capture_frame(&self, request: CaptureRequest) {
+        let CaptureRequest {
+            path,
+            format,
+            quality,
+            subtitles,
+            on_done,
+        } = request;
+        let Some(frame) = &self.latest_frame else {
+            on_done(Err(anyhow!("no frame decoded")));
+            return;
+        };
+        let time = frame.pts().unwrap_or(0) as f64 * f64::from(self.latest_time_base);
+        // Read from a second demuxer so that playback keeps its position
+        let cue = match subtitles.map(|source| active_cue(source, time, self.interrupter.clone())) {
+            Some(Err(e)) => {
+                on_done(Err(e));
+                return;
+            }
+            Some(Ok(cue)) => cue,
+            None => None,
+        };
+        let result = save_frame(frame, cue.as_ref(), &path, format, quality).map(|_| path);
+        on_done(result);
+    }
+
+    /// Frame to show before playback starts: the first frame, or the frame at `time`
+    pub fn preview_frame(&mut self, time: Option<f32>) -> Option<frame::Video> {
+        // Forward-only sources can only preview the first frame
+        let time = time.filter(|_| self.is_seekable());
+        match time {
+            None => {
+                let decoded_frame = self.latest_frame.take()?;
+                let rgb_frame = self.convert_frame(&decoded_frame);
+                self.latest_frame = Some(decoded_frame);
+                Some(rgb_frame)
+            }
+            Some(time) => {
+                let rgb_frame = self.seek_accurate(time).ok();
+                // Playback still starts from the beginning
+                self.seek(0.0);
+                rgb_frame
+            }
+        }
+    }
+
+    /// Seek to the keyframe before `time` and decode up to the first frame at or after it
+    pub fn seek_accurate(&mut self, time: f32) -> Result<frame::Video, anyhow::Error> {
+        self.seek(time);
+        loop {
+            let rgb_frame = self.next_frame(false)?;
+            let pts = self.latest_frame.as_ref().and_then(|f| f.pts()).unwrap_or(0);
+            let expected_pts = (time as f64 / f64::from(self.latest_time_base)) as i64;
+            if pts >= expected_pts {
+                return Ok(rgb_frame);
+            }
+        }
+    }
+
+    /// Decode the frame `delta` (+1/-1) frames away from the current one
+    pub fn step_frame(&mut self, delta: i32) -> Result<(frame::Video, FrameStep), anyhow::Error> {
+        let rgb_frame = if delta >= 0 {
+            self.next_frame(false)?
+        } else {
+            self.step_backward()?
+        };
+        let pts = self.latest_frame.as_ref().and_then(|f| f.pts()).unwrap_or(0);
+        let time = pts as f64 * f64::from(self.latest_time_base);
+        let step = FrameStep {
+            pts,
+            time: time as f32,
+            frame: (time * f64::from(self.frame_rate)).round() as i64,
+        };
+        Ok((rgb_frame, step))
+    }
+
+    fn step_backward(&mut self) -> Result<frame::Video, anyhow::Error> {
+        if !self.is_seekable() {
+            return Err(anyhow!("source is not seekable"));
+        }
+        let current_pts = self
+            .latest_frame
+            .as_ref()
+            .and_then(|f| f.pts())
+            .ok_or(anyhow!("no current frame"))?;
+        let current_time = current_pts as f64 * f64::from(self.latest_time_base);
+        let frame_duration = 1.0 / f64::from(self.frame_rate).max(1.0);
+        let mut seek_back = frame_duration;
+        // The keyframe before the previous frame may be far away, widen the seek until found
+        while seek_back < 60.0 {
+            let target = (current_time - seek_back).max(0.0);
+            self.seek(target as f32);
+            let mut previous = None;
+            loop {
+                self.next_frame(false)?;
+                let decoded = self.latest_frame.take().unwrap();
+                let time_base = self.latest_time_base;
+                let time = decoded.pts().unwrap_or(0) as f64 * f64::from(time_base);
+                if time >= current_time {
+                    // Resume after the previous frame, so the current one is shown next
+                    self.queued_frame = Some((decoded, time_base));
+                    break;
+                }
+                previous = Some((decoded, time_base));
+            }
+            if let Some((previous, time_base)) = previous {
+                let rgb_frame = self.convert_frame(&previous);
+                self.latest_frame = Some(previous);
+                self.latest_time_base = time_base;
+                return Ok(rgb_frame);
+            }
+            if target <= 0.0 {
+                break;
+            }
+            seek_back *= 4.0;
+        }
+        // Already at the first frame, show it again
+        self.next_frame(false)
+    }
+
+    fn convert_frame(&mut self, decoded_frame: &frame::Video) -> frame::Video {
+        let size = (decoded_frame.width() as f32, decoded_frame.height() as f32);
+        self.source_size.lock().unwrap().replace(size);
+        let settings = *self.scale_settings.lock().unwrap();
+        let rebuild_rescale_context = settings.quality != self.rescale_settings.quality
+            || need_rebuild(self.rescale_context.as_ref(), decoded_frame, &settings);
+        if rebuild_rescale_context {
+            self.rescale_context = Some(create_rescale_context(decoded_frame, &settings));
+            self.rescale_settings = settings;
+        }
+
+        let mut rgb_frame = ffmpeg_next::util::frame::Video::empty();
+        let rescaler = self.rescale_context.as_mut().unwrap();
+        rescaler.run(decoded_frame, &mut rgb_frame).unwrap();
+        rgb_frame
     }
 
     fn next_frame(&mut self, play_audio: bool) -> Result<frame::Video, anyhow::Error> {
-        let mut decoded_frame = ffmpeg_next::util::frame::Video::empty();
-        if self
-            .packet_decoder
-            .receive_frame(&mut decoded_frame)
-            .is_ok()
+        if let Some((decoded_frame, time_base)) = self
+            .queued_frame
+            .take()
+            .or_else(|| self.drained_frames.pop_front())
+            .or_else(|| self.receive_video_frame())
         {
-            let format = decoded_frame.format();
-            let rebuild_rescale_context = self
-                .rescale_context
-                .as_ref()
-                .map_or(true, |r| r.input().format != format);
-            if rebuild_rescale_context {
-                self.rescale_context = Some(create_rescale_context(&decoded_frame));
-            }
-
-            let mut rgb_frame = ffmpeg_next::util::frame::Video::empty();
-            let rescaler = self.rescale_context.as_mut().unwrap();
-            rescaler.run(&decoded_frame, &mut rgb_frame).unwrap();
+            let rgb_frame = self.convert_frame(&decoded_frame);
             self.latest_frame = Some(decoded_frame);
+            self.latest_time_base = time_base;
             return Ok(rgb_frame);
         }
 
-        let (stream, packet) = self.input_context.packets().next().ok_or(anyhow!("eof"))?;
+        let read_started = std::time::Instant::now();
+        let packet = self.input_context.read_packet()?;
+        if let Some(packet) = &packet {
+            self.abr.record(packet.size(), read_started.elapsed());
+            self.record_demuxed(packet);
+            self.commit_variant_switch(packet);
+        }
+        let Some(packet) = packet else {
+            if self.eof_sent {
+                self.eof_reached = true;
+                return Err(anyhow!("eof"));
+            }
+            // Flush the frames still buffered in the decoders
+            self.eof_sent = true;
+            self.packet_decoder.send_eof()?;
+            if let Some(audio_packet_decoder) = &mut self.audio_packet_decoder {
+                audio_packet_decoder.send_eof()?;
+            }
+            self.receive_audio_frames(play_audio)?;
+            return self.next_frame(play_audio);
+        };
 
-        if stream.index() == self.video_stream_index {
-            self.packet_decoder.send_packet(&packet)?;
-        } else if stream.index() == self.audio_stream_index {
-            let mut decoded_frame = ffmpeg_next::util::frame::Audio::empty();
-            self.audio_packet_decoder.send_packet(&packet).unwrap();
-            while self
-                .audio_packet_decoder
-                .receive_frame(&mut decoded_frame)
-                .is_ok()
-            {
-                // println!("sending audio frame");
-                if let Some(audio_frame_sender) = &mut self.audio_sender {
-                    if play_audio {
-                        audio_frame_sender.send(decoded_frame.clone())?;
+        if packet.stream() == self.video_stream_index {
+            if let Err(e) = self.packet_decoder.send_packet(&packet) {
+                // Live streams may start mid-GOP or lose packets, keep decoding
+                if !self.live {
+                    return Err(e.into());
+                }
+                println!("video decode error: {:?}", e);
+            }
+        } else if Some(packet.stream()) == self.audio_stream_index {
+            if let Some(audio_packet_decoder) = &mut self.audio_packet_decoder {
+                if let Err(e) = audio_packet_decoder.send_packet(&packet) {
+                    if !self.live {
+                        return Err(e.into());
                     }
+                    println!("audio decode error: {:?}", e);
                 }
             }
+            self.receive_audio_frames(play_audio)?;
         }
         self.next_frame(play_audio)
     }
+
+    /// Next decoded or filtered frame, with the time base of its pts
+    fn receive_video_frame(&mut self) -> Option<(frame::Video, Rational)> {
+        loop {
+            if let Some(filter) = &mut self.video_filter {
+                if let Some(filtered_frame) = filter.pull() {
+                    return Some((filtered_frame, filter.output_time_base()));
+                }
+            }
+            let mut decoded_frame = ffmpeg_next::util::frame::Video::empty();
+            match self.packet_decoder.receive_frame(&mut decoded_frame) {
+                Ok(()) => {}
+                Err(ffmpeg_next::Error::Eof) => {
+                    // Drain the frames the filters still hold back
+                    match &mut self.video_filter {
+                        Some(filter) if filter.flush() => continue,
+                        _ => return None,
+                    }
+                }
+                Err(_) => return None,
+            }
+            if !self.filter_frame(&decoded_frame) {
+                return Some((decoded_frame, self.timebase));
+            }
+        }
+    }
+
+    /// Push a decoded frame into the video filter, false if no filter is needed
+    fn filter_frame(&mut self, decoded_frame: &frame::Video) -> bool {
+        self.interlaced |= decoded_frame.is_interlaced();
+        let Some(spec) = filter_chain(
+            self.filter_options.deinterlace,
+            self.interlaced,
+            self.filter_options.video.as_deref(),
+        ) else {
+            self.video_filter = None;
+            return false;
+        };
+        let up_to_date = matches!(
+            &self.video_filter,
+            Some(filter) if filter.spec() == spec && filter.accepts(decoded_frame, self.timebase)
+        );
+        if !up_to_date {
+            // First frame, the filters have changed, or the resolution or pixel format has
+            match VideoFilter::new(&spec, decoded_frame, self.timebase) {
+                Ok(filter) => self.video_filter = Some(filter),
+                Err(e) => {
+                    self.fail_video_filter(spec, e);
+                    return false;
+                }
+            }
+        }
+        if let Err(e) = self.video_filter.as_mut().unwrap().push(decoded_frame) {
+            self.fail_video_filter(spec, e);
+            return false;
+        }
+        true
+    }
+
+    /// Remove a graph that failed to build or to filter, and report it
+    fn fail_video_filter(&mut self, spec: String, error: anyhow::Error) {
+        self.disable_video_filter();
+        self.filter_error = Some(FilterFailed {
+            kind: FilterKind::Video,
+            spec,
+            message: error.to_string(),
+        });
+    }
+
+    /// Give up on a failing graph, the user filters being the likely cause
+    fn disable_video_filter(&mut self) {
+        if self.filter_options.video.take().is_none() {
+            self.filter_options.deinterlace = DeinterlaceMode::Off;
+        }
+        self.video_filter = None;
+    }
+
+    fn receive_audio_frames(&mut self, play_audio: bool) -> Result<(), anyhow::Error> {
+        let Some(audio_packet_decoder) = &mut self.audio_packet_decoder else {
+            return Ok(());
+        };
+        let mut decoded_frame = ffmpeg_next::util::frame::Audio::empty();
+        while audio_packet_decoder.receive_frame(&mut decoded_frame).is_ok() {
+            // println!("sending audio frame");
+            if let Some(audio_frame_sender) = &mut self.audio_sender {
+                if play_audio {
+                    let generation = self.audio_generation.load(Ordering::SeqCst);
+                    audio_frame_sender.send(AudioMessage::Frame(decoded_frame.clone(), generation))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Video and audio streams to play, restricted to `variant` when given
+fn select_streams(input: &mut SourceInput, variant: Option<usize>) -> (Option<usize>, Option<usize>) {
+    match variant {
+        Some(id) => {
+            enable_variants(input, &[id]);
+            variant_streams(input, id)
+        }
+        None => {
+            let streams = input.streams();
+            (
+                streams.best(ffmpeg_next::media::Type::Video).map(|s| s.index()),
+                streams.best(ffmpeg_next::media::Type::Audio).map(|s| s.index()),
+            )
+        }
+    }
+}
+
+fn create_video_decoder(stream: &ffmpeg_next::Stream, live: bool) -> Result<Video, anyhow::Error> {
+    let mut decoder_context = ffmpeg_next::codec::Context::from_parameters(stream.parameters())?;
+    let mut threading_config = Config::default();
+    threading_config.count = num_cpus::get();
+    if live {
+        // Frame threading delays output by one frame per thread
+        threading_config.kind = threading::Type::Slice;
+        decoder_context.set_flags(ffmpeg_next::codec::Flags::LOW_DELAY);
+    } else {
+        threading_config.kind = threading::Type::Frame;
+    }
+    decoder_context.set_threading(threading_config);
+    Ok(decoder_context.decoder().video()?)
 }
 
-fn create_rescale_context(
-    frame: &ffmpeg_next::util::frame::Video,
-) -> ffmpeg_next::software::scaling::Context {
-    ffmpeg_next::software::scaling::Context::get(
-        frame.format(),
-        frame.width(),
-        frame.height(),
-        Pixel::RGBA,
-        frame.width(),
-        frame.height(),
-        ffmpeg_next::software::scaling::Flags::BILINEAR,
-    )
-    .unwrap()
+fn create_audio_decoder(stream: &ffmpeg_next::Stream) -> Result<Audio, anyhow::Error> {
+    let decoder_context = ffmpeg_next::codec::Context::from_parameters(stream.parameters())?;
+    Ok(decoder_context.decoder().audio()?)
 }
 
 unsafe impl Send for AudioPlayback<f32> {}
 
+enum AudioMessage {
+    /// Decoded frame, along with the seek generation it was decoded in
+    Frame(frame::Audio, usize),
+    /// Replace the filter graph, None to remove it
+    SetFilter(Option<String>),
+    /// Shift the audio by this many milliseconds against the video
+    SetDelay(i32),
+    /// Play out the buffered samples and stop, closing the channel stops immediately
+    Finish,
+}
+
+/// Processing of the audio output that can change while playing
+struct AudioSettings {
+    filter: Option<String>,
+    delay: i32,
+}
+
 struct AudioPlayback<T> {
     _stream: cpal::Stream,
-    frame_receiver: mpsc::Receiver<ffmpeg_next::util::frame::Audio>,
+    /// Measures what the stream plays, dropped after it
+    _meter_thread: MeterThread,
+    frame_receiver: mpsc::Receiver<AudioMessage>,
     sample_producer: Producer<T, Arc<SharedRb<T, Vec<MaybeUninit<T>>>>>,
     context: Context,
+    filter_spec: Option<String>,
+    /// Built for the current input format on the next frame
+    filter: Option<AudioFilter>,
+    /// Requested delay in milliseconds
+    delay: i32,
+    /// Shift of the queued samples towards `delay`
+    applied_delay: AudioDelay,
+    /// Seek generation of the player, frames of older generations are dropped
+    generation: Arc<AtomicUsize>,
+    current_generation: usize,
+    /// Asks the output callback to drop the samples it has not played yet
+    discard: Arc<AtomicBool>,
+    /// Receives the graphs that failed and were removed
+    filter_errors: mpsc::Sender<FilterFailed>,
 }
 
 impl<T: Send + Pod + SizedSample + 'static> AudioPlayback<T> {
     pub fn new(
         packet_decoder: &Audio,
-        frame_receiver: mpsc::Receiver<ffmpeg_next::util::frame::Audio>,
+        frame_receiver: mpsc::Receiver<AudioMessage>,
+        volume: SharedVolume,
+        settings: AudioSettings,
+        meter: AudioMeter,
+        generation: Arc<AtomicUsize>,
+        filter_errors: mpsc::Sender<FilterFailed>,
     ) -> Self {
         let buffer = HeapRb::new(4096 * 2);
         let (sample_producer, mut sample_consumer) = buffer.split();
@@ -302,13 +1345,29 @@ impl<T: Send + Pod + SizedSample + 'static> AudioPlayback<T> {
             ffmpeg_next::util::format::sample::Type::Packed,
         );
 
+        let (mut meter_tap, meter_thread) =
+            MeterThread::start(meter, config.channels() as usize, config.sample_rate().0);
+        let discard = Arc::new(AtomicBool::new(false));
+        let output_discard = discard.clone();
         let cpal_stream = device
             .build_output_stream(
                 &config.config(),
                 move |data: &mut [T], _| {
                     // println!("filling data");
+                    if output_discard.swap(false, Ordering::AcqRel) {
+                        sample_consumer.clear();
+                    }
                     let filled = sample_consumer.pop_slice(data);
                     data[filled..].fill(T::EQUILIBRIUM);
+                    // Applied on output so volume changes are heard immediately
+                    let volume = volume.get();
+                    if volume != 1.0 {
+                        let amp = T::Float::from_sample(volume);
+                        for sample in &mut data[..filled] {
+                            *sample = sample.mul_amp(amp);
+                        }
+                    }
+                    meter_tap.push(data);
                 },
                 move |err| {
                     eprintln!("error feeding audio stream to cpal: {}", err);
@@ -333,61 +1392,237 @@ impl<T: Send + Pod + SizedSample + 'static> AudioPlayback<T> {
             sample_producer,
             context: resampler,
             _stream: cpal_stream,
+            _meter_thread: meter_thread,
+            filter_spec: settings.filter,
+            filter: None,
+            delay: settings.delay,
+            applied_delay: AudioDelay::default(),
+            current_generation: generation.load(Ordering::SeqCst),
+            generation,
+            discard,
+            filter_errors,
         }
     }
 
     pub fn run(mut self) {
         loop {
             let frame = match self.frame_receiver.recv() {
-                Ok(frame) => frame,
+                Ok(AudioMessage::Frame(frame, generation)) => {
+                    if !self.enter_generation(generation) {
+                        continue;
+                    }
+                    frame
+                }
+                Ok(AudioMessage::SetFilter(spec)) => {
+                    self.filter_spec = spec;
+                    self.filter = None;
+                    continue;
+                }
+                Ok(AudioMessage::SetDelay(delay)) => {
+                    self.delay = delay;
+                    continue;
+                }
+                Ok(AudioMessage::Finish) => {
+                    // Release the samples held back by the filters, e.g. for loudnorm lookahead
+                    if let Some(mut filter) = self.filter.take() {
+                        filter.flush();
+                        while let Some(frame) = filter.pull() {
+                            self.play_frame(&frame);
+                        }
+                    }
+                    self.play_out();
+                    return;
+                }
                 Err(_) => return,
             };
             // println!("receive audio frame");
-            let input = self.context.input();
-            let output = self.context.output();
-            let input_rate = input.rate as i64;
-            let output_rate = output.rate as i64;
-            let output_format = output.format;
-            let output_channel_layout = output.channel_layout;
-            let out_samples = unsafe {
-                let delay = swr_get_delay(self.context.as_mut_ptr(), input_rate);
-                av_rescale_rnd(delay + frame.samples() as i64, output_rate, input_rate, AV_ROUND_UP)
-            } as usize;
-            let mut audio_frame = ffmpeg_next::util::frame::Audio::new(output_format, out_samples, output_channel_layout);
-            self.context.run(&frame, &mut audio_frame).unwrap();
-            // println!("resampled audio frame");
-
-            let expected_bytes =
-                audio_frame.samples() * audio_frame.channels() as usize * size_of::<T>();
-            let cpal_sample_data: &[T] =
-                bytemuck::cast_slice(&audio_frame.data(0)[..expected_bytes]);
-
-            while self.sample_producer.free_len() < cpal_sample_data.len() {
+            for frame in self.filter_frame(frame) {
+                self.play_frame(&frame);
+            }
+        }
+    }
+
+    /// Drop the queued audio when the player has seeked since the last frame. False if the
+    /// frame belongs to an older generation and must be dropped as well
+    fn enter_generation(&mut self, generation: usize) -> bool {
+        let latest = self.generation.load(Ordering::SeqCst);
+        if generation != latest {
+            return false;
+        }
+        if self.current_generation != latest {
+            self.current_generation = latest;
+            self.discard.store(true, Ordering::Release);
+            // Samples held back by the filters belong to the old position
+            self.filter = None;
+            // The discarded samples include the silence of the delay, insert it again
+            self.applied_delay = AudioDelay::default();
+        }
+        true
+    }
+
+    /// Run a decoded frame through the filter graph, returns the frames it released
+    fn filter_frame(&mut self, frame: frame::Audio) -> Vec<frame::Audio> {
+        let Some(spec) = self.filter_spec.clone() else {
+            return vec![frame];
+        };
+        let format = AudioFormat::of_frame(&frame);
+        if !matches!(&self.filter, Some(filter) if filter.accepts(format)) {
+            match AudioFilter::new(&spec, format) {
+                Ok(filter) => self.filter = Some(filter),
+                Err(e) => {
+                    self.fail_filter(spec, e);
+                    return vec![frame];
+                }
+            }
+        }
+        let filter = self.filter.as_mut().unwrap();
+        if let Err(e) = filter.push(&frame) {
+            self.fail_filter(spec, e);
+            return vec![frame];
+        }
+        std::iter::from_fn(|| filter.pull()).collect()
+    }
+
+    /// Remove a graph that failed to build or to filter, and report it to the player
+    fn fail_filter(&mut self, spec: String, error: anyhow::Error) {
+        self.filter_spec = None;
+        self.filter = None;
+        let _ = self.filter_errors.send(FilterFailed {
+            kind: FilterKind::Audio,
+            spec,
+            message: error.to_string(),
+        });
+    }
+
+    /// Resample a frame to the output format and queue it for playback
+    fn play_frame(&mut self, frame: &frame::Audio) {
+        let format = AudioFormat::of_frame(frame);
+        let input = self.context.input();
+        let output = self.context.output();
+        if (input.format, input.rate, input.channel_layout)
+            != (format.format, format.rate, format.channel_layout)
+        {
+            // Filters may change the sample format, rate or channels
+            self.context = Context::get(
+                format.format,
+                format.channel_layout,
+                format.rate,
+                output.format,
+                output.channel_layout,
+                output.rate,
+            )
+            .unwrap();
+        }
+        let input_rate = format.rate as i64;
+        let output_rate = output.rate as i64;
+        let output_format = output.format;
+        let output_channel_layout = output.channel_layout;
+        let out_samples = unsafe {
+            let delay = swr_get_delay(self.context.as_mut_ptr(), input_rate);
+            av_rescale_rnd(delay + frame.samples() as i64, output_rate, input_rate, AV_ROUND_UP)
+        } as usize;
+        let mut audio_frame = ffmpeg_next::util::frame::Audio::new(output_format, out_samples, output_channel_layout);
+        self.context.run(frame, &mut audio_frame).unwrap();
+        // println!("resampled audio frame");
+
+        let channels = audio_frame.channels() as usize;
+        let expected_bytes = audio_frame.samples() * channels * size_of::<T>();
+        let cpal_sample_data: &[T] =
+            bytemuck::cast_slice(&audio_frame.data(0)[..expected_bytes]);
+
+        self.apply_delay(channels, output_rate);
+        let skipped = self.applied_delay.skip(audio_frame.samples()) * channels;
+        // println!("pushing slice");
+        // Buffer the samples for playback
+        self.push_samples(&cpal_sample_data[skipped..]);
+    }
+
+    /// Shift the audio by the change of the delay: silence plays it later, dropping samples
+    /// plays it earlier. Takes effect immediately, the video clock is left alone
+    fn apply_delay(&mut self, channels: usize, rate: i64) {
+        let silence = self.applied_delay.set(self.delay, rate);
+        if silence > 0 {
+            self.push_samples(&vec![T::EQUILIBRIUM; silence * channels]);
+        }
+    }
+
+    /// Queue samples for the output, waiting while the buffer is full
+    fn push_samples(&mut self, mut data: &[T]) {
+        while !data.is_empty() {
+            let pushed = self.sample_producer.push_slice(data);
+            data = &data[pushed..];
+            if self.generation.load(Ordering::SeqCst) != self.current_generation {
+                // Seeked while waiting for room, the rest is outdated
+                return;
+            }
+            if !data.is_empty() {
                 //println!("audio sleeping");
                 thread::sleep(Duration::from_millis(16));
             }
+        }
+    }
+
+    /// Wait until the buffered samples have been consumed by the output device
+    fn play_out(&self) {
+        while !self.sample_producer.is_empty() {
+            thread::sleep(Duration::from_millis(16));
+        }
+        // Leave time for the device to play its own buffer
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Wait up to `timeout`, queueing the messages received meanwhile. Returns true as soon as one
+/// of them interrupts playback
+fn wait_for_message(
+    receiver: &mpsc::Receiver<ControlMessage>,
+    timeout: Duration,
+    pending: &mut VecDeque<ControlMessage>,
+) -> bool {
+    let deadline = std::time::Instant::now() + timeout;
+    while let Some(remaining) = deadline.checked_duration_since(std::time::Instant::now()) {
+        match receiver.recv_timeout(remaining) {
+            Ok(msg) => {
+                let interrupts = msg.interrupts();
+                pending.push_back(msg);
+                if interrupts {
+                    return true;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => break,
+            // Noticed by the next receive of the play loop
+            Err(RecvTimeoutError::Disconnected) => return true,
+        }
+    }
+    false
+}
 
-            // println!("pushing slice");
-            // Buffer the samples for playback
-            self.sample_producer.push_slice(cpal_sample_data);
+/// Store a new pending reply. The one it supersedes is resolved, a newer play or seek is not
+/// a failure of the older one
+fn replace_reply(pending: &mut Option<Reply>, reply: Option<Reply>) {
+    if let Some(reply) = reply {
+        if let Some(previous) = pending.replace(reply) {
+            previous(Ok(()));
         }
     }
 }
 
 struct StreamClock {
+    time_base: Rational,
     time_base_seconds: f64,
     start_time: std::time::Instant,
     start_pts: i64,
 }
 
 impl StreamClock {
-    fn new(time_base_seconds: Rational, start_pts: i64) -> Self {
-        let time_base_seconds =
-            time_base_seconds.numerator() as f64 / time_base_seconds.denominator() as f64;
+    /// Present `start_pts` after `delay`, a non-zero delay acts as a jitter buffer
+    fn new(time_base: Rational, start_pts: i64, delay: Duration) -> Self {
+        let time_base_seconds = time_base.numerator() as f64 / time_base.denominator() as f64;
 
-        let start_time = std::time::Instant::now();
+        let start_time = std::time::Instant::now() + delay;
 
         Self {
+            time_base,
             time_base_seconds,
             start_time,
             start_pts,
@@ -397,18 +1632,26 @@ impl StreamClock {
     fn convert_pts_to_instant(&self, pts: Option<i64>) -> Option<std::time::Duration> {
         pts.and_then(|pts| {
             let pts_since_start = std::time::Duration::from_secs_f64(
-                (pts - self.start_pts) as f64 * self.time_base_seconds,
+                ((pts - self.start_pts) as f64 * self.time_base_seconds).max(0.0),
             );
             self.start_time.checked_add(pts_since_start)
         })
         .map(|absolute_pts| absolute_pts.duration_since(std::time::Instant::now()))
     }
 
-    fn convert_pts_to_time(&self, pts: i64) -> f64 {
-        pts as f64 * self.time_base_seconds
+    /// Seconds the frame is behind its presentation time, negative when early
+    fn lateness(&self, pts: i64) -> f64 {
+        let due = (pts - self.start_pts) as f64 * self.time_base_seconds;
+        let now = std::time::Instant::now();
+        let elapsed = if now >= self.start_time {
+            (now - self.start_time).as_secs_f64()
+        } else {
+            -(self.start_time - now).as_secs_f64()
+        };
+        elapsed - due
     }
 
-    fn convert_time_to_pts(&self, time: f64) -> i64 {
-        (time / self.time_base_seconds) as i64
+    fn convert_pts_to_time(&self, pts: i64) -> f64 {
+        pts as f64 * self.time_base_seconds
     }
 }
\ No newline at end of file
@@ -1,57 +1,611 @@
-use crate::player::{ControlMessage, Meta};
-use std::sync::mpsc;
-use std::sync::mpsc::Sender;
+use crate::audio_filter::AudioFilter;
+use crate::player::{
+    ControlMessage, FrameStep, LoadRequest, Meta, PlayCallbacks, PlayExit, Reply,
+};
+use crate::interrupt::{Interrupted, Interrupter};
+use crate::scaling::ScaleSettings;
+use crate::snapshot::CaptureRequest;
+use crate::state::SharedVolume;
+use crate::video_filter::{DeinterlaceMode, VideoFilter};
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::mpsc::{Receiver, SendError, Sender};
 use std::thread;
+use std::thread::JoinHandle;
 
 pub struct PlayerThread {
-    sender: Sender<ControlMessage>,
+    /// None once dropped, closing the channel ends the thread
+    sender: Option<Sender<ControlMessage>>,
+    handle: Option<JoinHandle<()>>,
+    interrupter: Interrupter,
 }
 
 pub struct PlayParams {
-    pub path: String,
-    pub on_meta_loaded: Box<dyn FnOnce(Meta) + Send + 'static>,
-    pub renderer: Box<dyn FnMut(ffmpeg_next::util::frame::Video) + Send + 'static>,
-    pub on_progress: Box<dyn FnMut(f32) + Send + 'static>,
-    pub on_stop: Box<dyn FnMut() + Send + 'static>,
+    pub scale_settings: Arc<Mutex<ScaleSettings>>,
+    /// Updated with the size of the frames before scaling
+    pub source_size: Arc<Mutex<Option<(f32, f32)>>>,
+    pub volume: SharedVolume,
+    /// Aborts blocking IO when control messages are sent
+    pub interrupter: Interrupter,
+    pub on_meta_loaded: Box<dyn FnMut(Meta) + Send + 'static>,
+    pub callbacks: PlayCallbacks,
 }
 
 impl PlayerThread {
+    /// Start an idle player thread, sources are opened with `load`
     pub fn start(params: PlayParams) -> Self {
         let (sender, receiver) = mpsc::channel();
-        thread::spawn(move || {
-            let mut player = crate::player::PlayServer::new(params.path.clone());
-            let width = player.get_width().unwrap();
-            let height = player.get_height().unwrap();
-            let duration = player.get_duration();
-            let meta = Meta {
-                width,
-                height,
-                duration,
-            };
-            (params.on_meta_loaded)(meta);
-            player.play(
-                params.renderer,
-                params.on_progress,
-                receiver,
-                params.on_stop,
-            );
+        let interrupter = params.interrupter.clone();
+        let handle = thread::spawn(move || {
+            let PlayParams {
+                scale_settings,
+                source_size,
+                volume,
+                interrupter,
+                mut on_meta_loaded,
+                mut callbacks,
+            } = params;
+            let mut request = wait_for_load(&receiver, &interrupter);
+            while let Some(load) = request {
+                let player = crate::player::PlayServer::new(
+                    load.source,
+                    load.options,
+                    interrupter.clone(),
+                    load.filters,
+                    scale_settings.clone(),
+                    source_size.clone(),
+                    volume.clone(),
+                );
+                let mut player = match player {
+                    Ok(player) => player,
+                    Err(e) => {
+                        if let Some(reply) = load.reply {
+                            reply(Err(anyhow::anyhow!("{}", e)));
+                        }
+                        // Opening was aborted by the next request, not a source error
+                        if !e.is::<Interrupted>() {
+                            (callbacks.on_error)(e);
+                        }
+                        request = wait_for_load(&receiver, &interrupter);
+                        continue;
+                    }
+                };
+                let width = player.get_width().unwrap();
+                let height = player.get_height().unwrap();
+                let duration = player.get_duration();
+                let meta = Meta {
+                    width,
+                    height,
+                    duration,
+                    seekable: player.is_seekable(),
+                    live: player.is_live(),
+                    variants: player.get_variants(),
+                };
+                on_meta_loaded(meta);
+                if let Some(reply) = load.reply {
+                    reply(Ok(()));
+                }
+                if let Some(frame) = player.preview_frame(load.preview_time) {
+                    (callbacks.renderer)(frame, true);
+                }
+                if let Some(buffered) = player.get_buffered() {
+                    (callbacks.on_buffered)(buffered);
+                }
+                request = match player.play(&mut callbacks, &receiver) {
+                    PlayExit::Load(request) => Some(request),
+                    PlayExit::Stopped => wait_for_load(&receiver, &interrupter),
+                    PlayExit::Failed(e) => {
+                        (callbacks.on_error)(e);
+                        wait_for_load(&receiver, &interrupter)
+                    }
+                    PlayExit::Disconnected => None,
+                };
+            }
         });
-        Self { sender }
+        Self {
+            sender: Some(sender),
+            handle: Some(handle),
+            interrupter,
+        }
+    }
+
+    /// Requested by the control messages that abort blocking IO, e.g. for readers of pushed
+    /// data that wait outside of ffmpeg
+    pub fn interrupter(&self) -> Interrupter {
+        self.interrupter.clone()
+    }
+
+    pub fn load(&self, request: LoadRequest) {
+        self.send_with_reply(ControlMessage::Load(request));
+    }
+
+    pub fn seek(&mut self, time: f32, reply: Option<Reply>) {
+        self.send_with_reply(ControlMessage::Seek(time, reply));
     }
 
-    pub fn seek(&mut self, time: f32) {
-        let _ = self.sender.send(ControlMessage::Seek(time));
+    pub fn play(&self, reply: Option<Reply>) {
+        self.send_with_reply(ControlMessage::Play(reply));
     }
 
-    pub fn play(&self) {
-        let _ = self.sender.send(ControlMessage::Play);
+    fn send(&self, msg: ControlMessage) -> Result<(), SendError<ControlMessage>> {
+        let interrupts = msg.interrupts();
+        if interrupts {
+            self.interrupter.request();
+        }
+        let result = match &self.sender {
+            Some(sender) => sender.send(msg),
+            None => Err(SendError(msg)),
+        };
+        if result.is_err() && interrupts {
+            self.interrupter.acknowledge();
+        }
+        result
+    }
+
+    fn send_with_reply(&self, msg: ControlMessage) {
+        if let Err(e) = self.send(msg) {
+            if let ControlMessage::Play(Some(reply))
+            | ControlMessage::Seek(_, Some(reply))
+            | ControlMessage::SetVideoFilter(_, reply)
+            | ControlMessage::SetAudioFilter(_, reply)
+            | ControlMessage::Load(LoadRequest {
+                reply: Some(reply), ..
+            }) = e.0
+            {
+                reply(Err(anyhow::anyhow!("player stopped")));
+            }
+        }
     }
 
     pub fn pause(&self) {
-        let _ = self.sender.send(ControlMessage::Pause);
+        let _ = self.send(ControlMessage::Pause);
     }
 
     pub fn stop(&self) {
-        let _ = self.sender.send(ControlMessage::Stop);
+        let _ = self.send(ControlMessage::Stop);
+    }
+
+    pub fn step_frame(
+        &self,
+        delta: i32,
+        on_done: Box<dyn FnOnce(Result<FrameStep, anyhow::Error>) + Send + 'static>,
+    ) {
+        let _ = self.send(ControlMessage::StepFrame(delta, on_done));
+    }
+
+    /// Play the audio `delay` milliseconds after (positive) or before (negative) the video
+    pub fn set_audio_delay(&self, delay: i32) {
+        let _ = self.send(ControlMessage::SetAudioDelay(delay));
+    }
+
+    pub fn set_loop(&self, value: bool) {
+        let _ = self.send(ControlMessage::SetLoop(value));
+    }
+
+    pub fn set_loop_range(&self, range: Option<(f32, f32)>) {
+        let _ = self.send(ControlMessage::SetLoopRange(range));
+    }
+
+    /// Play variant `id` of an adaptive source, None to select from the throughput
+    pub fn select_variant(&self, id: Option<usize>) {
+        let _ = self.send(ControlMessage::SelectVariant(id));
+    }
+
+    /// Filter the decoded frames with a libavfilter graph, None to remove it
+    pub fn set_video_filter(&self, spec: Option<String>, reply: Reply) {
+        self.send_with_reply(ControlMessage::SetVideoFilter(spec, reply));
+    }
+
+    /// Filter the decoded audio with a libavfilter graph, None to remove it
+    pub fn set_audio_filter(&self, spec: Option<String>, reply: Reply) {
+        self.send_with_reply(ControlMessage::SetAudioFilter(spec, reply));
+    }
+
+    pub fn set_deinterlace(&self, mode: DeinterlaceMode) {
+        let _ = self.send(ControlMessage::SetDeinterlace(mode));
+    }
+
+    pub fn capture_frame(&self, request: CaptureRequest) {
+        if let Err(e) = self.send(ControlMessage::Capture(request)) {
+            if let ControlMessage::Capture(request) = e.0 {
+                (request.on_done)(Err(anyhow::anyhow!("player stopped")));
+            }
+        }
+    }
+}
+
+impl Drop for PlayerThread {
+    fn drop(&mut self) {
+        let _ = self.send(ControlMessage::Stop);
+        // Closing the channel ends the thread once playback has stopped
+        self.sender = None;
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Block until the next source is requested, None when the channel is closed
+fn wait_for_load(
+    receiver: &Receiver<ControlMessage>,
+    interrupter: &Interrupter,
+) -> Option<LoadRequest> {
+    for msg in receiver.iter() {
+        if msg.interrupts() {
+            interrupter.acknowledge();
+        }
+        match msg {
+            ControlMessage::Load(request) => return Some(request),
+            ControlMessage::Play(Some(reply)) | ControlMessage::Seek(_, Some(reply)) => {
+                reply(Err(anyhow::anyhow!("no video loaded")));
+            }
+            ControlMessage::Capture(request) => {
+                (request.on_done)(Err(anyhow::anyhow!("no video loaded")));
+            }
+            ControlMessage::StepFrame(_, on_done) => {
+                on_done(Err(anyhow::anyhow!("no video loaded")));
+            }
+            // The element passes the filters along with its next load request, check them now
+            // so that an invalid description is not kept until then
+            ControlMessage::SetVideoFilter(spec, reply) => {
+                reply(spec.as_deref().map_or(Ok(()), VideoFilter::check))
+            }
+            ControlMessage::SetAudioFilter(spec, reply) => {
+                reply(spec.as_deref().map_or(Ok(()), AudioFilter::check))
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::{AnimationFormat, AnimationParams};
+    use crate::byte_stream::ByteStream;
+    use crate::export::{ClipExporter, ExportMode, ExportParams};
+    use crate::levels::{AudioMeter, MeterSettings};
+    use crate::player::FilterOptions;
+    use crate::live::LiveMode;
+    use crate::source::{MediaSource, OpenOptions};
+    use crate::test_media::{temp_dir, TestServer, TestVideo};
+    use crate::thumbnails::{ThumbnailExtractor, ThumbnailParams};
+    use std::sync::atomic::Ordering;
+    use std::time::{Duration, Instant};
+
+    /// What the player reported, in order
+    #[derive(Clone, Debug, PartialEq)]
+    enum Event {
+        Meta,
+        /// Width of a presented frame
+        Frame(u32),
+        Error(String),
+        Progress(f32),
+        Buffered(f32),
+        Stopped,
+        Ended,
+    }
+
+    fn start_player(events: mpsc::Sender<Event>) -> PlayerThread {
+        let send = move |event| {
+            let events = events.clone();
+            move || {
+                let _ = events.send(event.clone());
+            }
+        };
+        let error_events = events.clone();
+        let frame_events = events.clone();
+        let progress_events = events.clone();
+        let buffered_events = events.clone();
+        PlayerThread::start(PlayParams {
+            scale_settings: Default::default(),
+            source_size: Default::default(),
+            volume: SharedVolume::default(),
+            interrupter: Interrupter::default(),
+            on_meta_loaded: Box::new({
+                let mut meta = send(Event::Meta);
+                move |_| meta()
+            }),
+            callbacks: PlayCallbacks {
+                renderer: Box::new(move |frame, preview| {
+                    if !preview {
+                        let _ = frame_events.send(Event::Frame(frame.width()));
+                    }
+                }),
+                on_progress: Box::new(move |time| {
+                    let _ = progress_events.send(Event::Progress(time));
+                }),
+                on_buffered: Box::new(move |end| {
+                    let _ = buffered_events.send(Event::Buffered(end));
+                }),
+                on_stop: Box::new(send(Event::Stopped)),
+                on_ended: Box::new(send(Event::Ended)),
+                on_play: Box::new(|| {}),
+                on_pause: Box::new(|| {}),
+                on_seeking: Box::new(|| {}),
+                on_error: Box::new(move |e| {
+                    let _ = error_events.send(Event::Error(e.to_string()));
+                }),
+                audio_meter: AudioMeter {
+                    settings: MeterSettings::default(),
+                    on_levels: Arc::new(Mutex::new(Box::new(|_| {}))),
+                },
+            },
+        })
+    }
+
+    fn load(player: &PlayerThread, url: String, options: OpenOptions) {
+        player.load(LoadRequest {
+            source: MediaSource::Url(url),
+            options,
+            preview_time: None,
+            filters: FilterOptions::default(),
+            reply: None,
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    fn thread_count() -> usize {
+        std::fs::read_dir("/proc/self/task").unwrap().count()
+    }
+
+    /// Whether this is a child process running `test` alone. Otherwise runs it in one and
+    /// checks that it passed, so that the threads of tests running in parallel are not counted
+    #[cfg(target_os = "linux")]
+    fn isolated(test: &str) -> bool {
+        if std::env::var_os("DEFT_VIDEO_ISOLATED_TEST").is_some() {
+            return true;
+        }
+        let (_, module) = module_path!().split_once("::").unwrap();
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([&format!("{}::{}", module, test), "--exact", "--test-threads=1"])
+            .env("DEFT_VIDEO_ISOLATED_TEST", "1")
+            .status()
+            .unwrap();
+        assert!(status.success(), "{} failed in isolation", test);
+        false
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn dropping_players_leaves_no_threads() {
+        if !isolated("dropping_players_leaves_no_threads") {
+            return;
+        }
+        let before = thread_count();
+        for i in 0..100 {
+            let (events, _) = mpsc::channel();
+            let player = start_player(events);
+            if i % 2 == 0 {
+                load(&player, "/nonexistent/video.mp4".to_string(), OpenOptions::default());
+                player.play(None);
+            }
+        }
+        assert_eq!(thread_count(), before);
+    }
+
+    /// What the element releases on destroy: the player and the thumbnail, export and
+    /// animation jobs, all still running
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn releasing_player_and_jobs_leaves_no_threads() {
+        if !isolated("releasing_player_and_jobs_leaves_no_threads") {
+            return;
+        }
+        let dir = temp_dir("release-threads");
+        let path = dir.join("video.mkv");
+        TestVideo {
+            frames: 500,
+            ..TestVideo::default()
+        }
+        .write(&path, None, &[]);
+        let src = || MediaSource::Url(path.to_str().unwrap().to_string());
+        let output = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        let before = thread_count();
+        let (events, receiver) = mpsc::channel();
+        let player = start_player(events);
+        load(&player, path.to_str().unwrap().to_string(), OpenOptions::default());
+        player.play(None);
+        while receiver.recv_timeout(Duration::from_secs(10)).unwrap() != Event::Meta {}
+        let thumbnails = ThumbnailExtractor::start(ThumbnailParams {
+            src: src(),
+            output: output("sheet.png"),
+            count: 20,
+            tile_width: 32,
+            columns: 5,
+            on_progress: Box::new(|_| {}),
+            on_done: Box::new(|_| {}),
+        });
+        let export = ClipExporter::start(ExportParams {
+            src: src(),
+            output: output("clip.mkv"),
+            start: 0.0,
+            end: 20.0,
+            mode: ExportMode::Encode,
+            video_filter: None,
+            on_progress: Box::new(|_| {}),
+            on_done: Box::new(|_| {}),
+        });
+        let animation = ClipExporter::start_animation(AnimationParams {
+            src: src(),
+            output: output("clip.gif"),
+            start: 0.0,
+            end: 20.0,
+            fps: 10.0,
+            width: 0,
+            format: AnimationFormat::Gif,
+            video_filter: None,
+            on_progress: Box::new(|_| {}),
+            on_done: Box::new(|_| {}),
+        });
+        assert!(thread_count() > before);
+        drop((thumbnails, export, animation, player));
+        assert_eq!(thread_count(), before);
+    }
+
+    /// A server that stops sending mid-file fails playback with an error, not a stop
+    #[test]
+    fn read_timeout_during_playback_is_an_error() {
+        let dir = temp_dir("read-timeout");
+        let path = dir.join("video.ts");
+        TestVideo {
+            frames: 125,
+            ..TestVideo::default()
+        }
+        .write(&path, None, &[]);
+        let server = TestServer::start(&dir);
+        let size = std::fs::metadata(&path).unwrap().len() as usize;
+        server.stall_after.store(size / 2, Ordering::SeqCst);
+
+        let (events, receiver) = mpsc::channel();
+        let player = start_player(events);
+        let options = OpenOptions {
+            read_timeout: Some(Duration::from_secs(1)),
+            live: LiveMode::Off,
+            ..OpenOptions::default()
+        };
+        load(&player, server.url("video.ts"), options);
+        player.play(None);
+        let mut buffered = None;
+        let error = loop {
+            match receiver.recv_timeout(Duration::from_secs(20)).unwrap() {
+                Event::Error(error) => break error,
+                Event::Buffered(end) => buffered = Some(end),
+                Event::Stopped | Event::Ended => panic!("stall reported as the end"),
+                _ => {}
+            }
+        };
+        assert!(!error.is_empty());
+        // Taken from the packets read before the stall, mpegts starts at 1.4s
+        let buffered = buffered.expect("no buffered time");
+        assert!(buffered > 0.0 && buffered < 6.4, "{}", buffered);
+        drop(player);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// A file played as live ends at its end instead of reconnecting
+    #[test]
+    fn live_file_ends() {
+        let dir = temp_dir("live-file");
+        let path = dir.join("video.mkv");
+        TestVideo {
+            frames: 10,
+            ..TestVideo::default()
+        }
+        .write(&path, None, &[]);
+        let (events, receiver) = mpsc::channel();
+        let player = start_player(events);
+        let options = OpenOptions {
+            live: LiveMode::On,
+            ..OpenOptions::default()
+        };
+        load(&player, path.to_str().unwrap().to_string(), options);
+        player.play(None);
+        loop {
+            match receiver.recv_timeout(Duration::from_secs(10)).unwrap() {
+                Event::Ended => break,
+                Event::Error(error) => panic!("{}", error),
+                Event::Stopped => panic!("stopped before the end"),
+                _ => {}
+            }
+        }
+        drop(player);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// Switching variants of an HLS master playlist served over HTTP presents the frames the
+    /// old decoder still holds, then continues with the new variant
+    #[test]
+    fn variant_switch_over_http() {
+        let dir = temp_dir("variants");
+        for (name, width, height) in [("low", 64, 48), ("high", 128, 96)] {
+            std::fs::create_dir(dir.join(name)).unwrap();
+            TestVideo {
+                width,
+                height,
+                frames: 100,
+                ..TestVideo::default()
+            }
+            .write(
+                &dir.join(name).join("index.m3u8"),
+                None,
+                &[("hls_time", "1"), ("hls_playlist_type", "vod")],
+            );
+        }
+        std::fs::write(
+            dir.join("master.m3u8"),
+            "#EXTM3U\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=100000,RESOLUTION=64x48\nlow/index.m3u8\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=400000,RESOLUTION=128x96\nhigh/index.m3u8\n",
+        )
+        .unwrap();
+        let server = TestServer::start(&dir);
+
+        let (events, receiver) = mpsc::channel();
+        let player = start_player(events);
+        load(&player, server.url("master.m3u8"), OpenOptions::default());
+        player.select_variant(Some(0));
+        player.play(None);
+        let mut widths = Vec::new();
+        let mut last_time = 0.0;
+        loop {
+            match receiver.recv_timeout(Duration::from_secs(20)).unwrap() {
+                Event::Frame(width) => {
+                    widths.push(width);
+                    if widths.len() == 10 {
+                        player.select_variant(Some(1));
+                    }
+                }
+                Event::Progress(time) => last_time = time,
+                Event::Ended => break,
+                Event::Error(error) => panic!("{}", error),
+                Event::Stopped => panic!("stopped before the end"),
+                _ => {}
+            }
+        }
+        assert!(server.requests.load(Ordering::SeqCst) > 2);
+        // All frames of the old variant come before the first of the new one
+        let switched = widths.iter().position(|&w| w == 128).expect("variant not switched");
+        assert!(widths[..switched].iter().all(|&w| w == 64));
+        assert!(widths[switched..].iter().all(|&w| w == 128));
+        assert!(last_time > 3.0, "{}", last_time);
+        drop(player);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    /// A pushed stream that runs dry does not keep the player from stopping
+    #[test]
+    fn drop_while_stream_reader_is_blocked() {
+        let dir = temp_dir("starved-stream");
+        let path = dir.join("video.mkv");
+        TestVideo::default().write(&path, None, &[]);
+        let bytes = std::fs::read(&path).unwrap();
+
+        let (events, _receiver) = mpsc::channel();
+        let player = start_player(events);
+        let stream = ByteStream::default();
+        let (waiting_sender, waiting) = mpsc::channel();
+        let reader = stream.reader(
+            player.interrupter(),
+            Box::new(move || {
+                let _ = waiting_sender.send(());
+            }),
+            Box::new(|| {}),
+        );
+        stream.append(&bytes[..bytes.len() / 2]);
+        player.load(LoadRequest {
+            source: MediaSource::Reader(Box::new(reader)),
+            options: OpenOptions::default(),
+            preview_time: None,
+            filters: FilterOptions::default(),
+            reply: None,
+        });
+        player.play(None);
+        waiting.recv_timeout(Duration::from_secs(10)).unwrap();
+        let started = Instant::now();
+        drop(player);
+        assert!(started.elapsed() < Duration::from_secs(2), "{:?}", started.elapsed());
+        let _ = std::fs::remove_dir_all(dir);
     }
 }
@@ -0,0 +1,268 @@
+use crate::interrupt::Interrupter;
+use crate::source::{MediaSource, OpenOptions, SourceInput};
+use anyhow::anyhow;
+use deft::js_func;
+use ffmpeg_next::ffi::{
+    av_channel_layout_describe, av_color_primaries_name, av_color_range_name, av_color_space_name,
+    av_color_transfer_name, av_get_pix_fmt_name, av_get_sample_fmt_name, avcodec_profile_name,
+    AVCodecParameters, AVPixelFormat, AVSampleFormat, AV_TIME_BASE,
+};
+use ffmpeg_next::{media, DictionaryRef, Rational, Stream};
+use futures_channel::oneshot;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::{c_char, CStr};
+use std::thread;
+
+/// Container and stream metadata of a source, read without setting up playback
+#[derive(Serialize, Clone, Debug)]
+pub struct ProbeInfo {
+    /// Demuxer short names, e.g. `mov,mp4,m4a,3gp,3g2,mj2`
+    pub format: String,
+    pub format_long_name: String,
+    /// Seconds, None when unknown, e.g. for live streams
+    pub duration: Option<f32>,
+    /// Bits per second of the whole source
+    pub bit_rate: Option<i64>,
+    pub tags: BTreeMap<String, String>,
+    pub chapters: Vec<ChapterInfo>,
+    pub streams: Vec<StreamInfo>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ChapterInfo {
+    pub id: i64,
+    /// Seconds
+    pub start: f32,
+    pub end: f32,
+    pub title: Option<String>,
+}
+
+/// Fields that do not apply to the stream type are None
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct StreamInfo {
+    pub index: usize,
+    /// One of video, audio, subtitle, data, attachment or unknown
+    pub kind: &'static str,
+    pub codec: String,
+    pub profile: Option<String>,
+    pub bit_rate: Option<i64>,
+    /// Seconds
+    pub duration: Option<f32>,
+    pub language: Option<String>,
+    pub tags: BTreeMap<String, String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Average frame rate
+    pub fps: Option<f32>,
+    /// Sample aspect ratio as numerator and denominator
+    pub sar: Option<(i32, i32)>,
+    pub pixel_format: Option<String>,
+    pub color_range: Option<String>,
+    pub color_space: Option<String>,
+    pub color_primaries: Option<String>,
+    pub color_transfer: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+    pub channel_layout: Option<String>,
+    pub sample_format: Option<String>,
+}
+
+/// Read the metadata of a source. Only the demuxer is opened, as needed to fill in the stream
+/// parameters; no decoder or audio device is set up
+pub fn probe(source: MediaSource) -> Result<ProbeInfo, anyhow::Error> {
+    let input = SourceInput::open(source, OpenOptions::default(), Interrupter::default())?;
+    let format = input.format();
+    let duration = match input.duration() {
+        d if d > 0 => Some((d as f64 / AV_TIME_BASE as f64) as f32),
+        _ => None,
+    };
+    let chapters = input
+        .chapters()
+        .map(|chapter| {
+            let time_base = f64::from(chapter.time_base());
+            ChapterInfo {
+                id: chapter.id(),
+                start: (chapter.start() as f64 * time_base) as f32,
+                end: (chapter.end() as f64 * time_base) as f32,
+                title: chapter.metadata().get("title").map(str::to_string),
+            }
+        })
+        .collect();
+    Ok(ProbeInfo {
+        format: format.name().to_string(),
+        format_long_name: format.description().to_string(),
+        duration,
+        bit_rate: positive(input.bit_rate()),
+        tags: collect_tags(input.metadata()),
+        chapters,
+        streams: input.streams().map(|s| stream_info(&s)).collect(),
+    })
+}
+
+/// Result of the `video_probe` JS function, exactly one of the fields is set
+#[derive(Serialize)]
+pub struct ProbeResult {
+    info: Option<ProbeInfo>,
+    error: Option<String>,
+}
+
+/// Metadata of the file or URL at `path`. Opening may take up to the open timeout, so it runs
+/// on its own thread and the returned promise resolves once the source has been read
+#[js_func]
+pub async fn video_probe(path: String) -> ProbeResult {
+    let (sender, receiver) = oneshot::channel();
+    thread::spawn(move || {
+        let _ = sender.send(probe(MediaSource::Url(path)));
+    });
+    let result = receiver
+        .await
+        .unwrap_or_else(|_| Err(anyhow!("probe thread exited")));
+    match result {
+        Ok(info) => ProbeResult {
+            info: Some(info),
+            error: None,
+        },
+        Err(e) => ProbeResult {
+            info: None,
+            error: Some(e.to_string()),
+        },
+    }
+}
+
+fn stream_info(stream: &Stream) -> StreamInfo {
+    let parameters = stream.parameters();
+    let tags = collect_tags(stream.metadata());
+    let mut info = StreamInfo {
+        index: stream.index(),
+        kind: match parameters.medium() {
+            media::Type::Video => "video",
+            media::Type::Audio => "audio",
+            media::Type::Subtitle => "subtitle",
+            media::Type::Data => "data",
+            media::Type::Attachment => "attachment",
+            media::Type::Unknown => "unknown",
+        },
+        codec: parameters.id().name().to_string(),
+        duration: match stream.duration() {
+            d if d > 0 => Some((d as f64 * f64::from(stream.time_base())) as f32),
+            _ => None,
+        },
+        language: tags.get("language").cloned(),
+        tags,
+        ..Default::default()
+    };
+    unsafe {
+        let codecpar = parameters.as_ptr();
+        info.profile = c_string(avcodec_profile_name(
+            (*codecpar).codec_id,
+            (*codecpar).profile,
+        ));
+        info.bit_rate = positive((*codecpar).bit_rate);
+        match parameters.medium() {
+            media::Type::Video => video_info(&mut info, stream, codecpar),
+            media::Type::Audio => audio_info(&mut info, codecpar),
+            _ => {}
+        }
+    }
+    info
+}
+
+unsafe fn video_info(info: &mut StreamInfo, stream: &Stream, codecpar: *const AVCodecParameters) {
+    info.width = Some((*codecpar).width as u32);
+    info.height = Some((*codecpar).height as u32);
+    info.fps = [stream.avg_frame_rate(), stream.rate()]
+        .into_iter()
+        .find(|r| r.numerator() > 0 && r.denominator() > 0)
+        .map(|r| f64::from(r) as f32);
+    // Muxers may only set the aspect ratio on the stream
+    info.sar = [(*codecpar).sample_aspect_ratio.into(), stream_sar(stream)]
+        .into_iter()
+        .find(|r: &Rational| r.numerator() > 0 && r.denominator() > 0)
+        .map(|r| (r.numerator(), r.denominator()));
+    let format: AVPixelFormat = std::mem::transmute((*codecpar).format);
+    info.pixel_format = c_string(av_get_pix_fmt_name(format));
+    info.color_range = c_string(av_color_range_name((*codecpar).color_range));
+    info.color_space = c_string(av_color_space_name((*codecpar).color_space));
+    info.color_primaries = c_string(av_color_primaries_name((*codecpar).color_primaries));
+    info.color_transfer = c_string(av_color_transfer_name((*codecpar).color_trc));
+}
+
+unsafe fn audio_info(info: &mut StreamInfo, codecpar: *const AVCodecParameters) {
+    info.sample_rate = Some((*codecpar).sample_rate as u32);
+    let channels = (*codecpar).ch_layout.nb_channels;
+    info.channels = Some(channels as u32);
+    if channels > 0 {
+        let mut description = [0 as c_char; 64];
+        let len = av_channel_layout_describe(
+            &(*codecpar).ch_layout,
+            description.as_mut_ptr(),
+            description.len(),
+        );
+        if len > 0 {
+            info.channel_layout = c_string(description.as_ptr());
+        }
+    }
+    let format: AVSampleFormat = std::mem::transmute((*codecpar).format);
+    info.sample_format = c_string(av_get_sample_fmt_name(format));
+}
+
+fn stream_sar(stream: &Stream) -> Rational {
+    unsafe { (*stream.as_ptr()).sample_aspect_ratio.into() }
+}
+
+fn collect_tags(metadata: DictionaryRef) -> BTreeMap<String, String> {
+    metadata
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+fn positive(value: i64) -> Option<i64> {
+    (value > 0).then_some(value)
+}
+
+/// Copy a C string owned by ffmpeg, None for null, e.g. names of unspecified values
+unsafe fn c_string(value: *const c_char) -> Option<String> {
+    if value.is_null() {
+        return None;
+    }
+    Some(CStr::from_ptr(value).to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_media::{temp_dir, TestVideo};
+
+    #[test]
+    fn probe_reports_streams_of_a_file() {
+        let dir = temp_dir("probe");
+        let path = dir.join("video.mkv");
+        TestVideo {
+            width: 96,
+            height: 64,
+            frames: 50,
+            ..TestVideo::default()
+        }
+        .write(&path, None, &[]);
+        let info = probe(MediaSource::Url(path.to_str().unwrap().to_string())).unwrap();
+        assert!(info.format.contains("matroska"), "{}", info.format);
+        let duration = info.duration.unwrap();
+        assert!((duration - 2.0).abs() < 0.1, "{}", duration);
+        assert_eq!(info.streams.len(), 1);
+        let stream = &info.streams[0];
+        assert_eq!(stream.kind, "video");
+        assert_eq!(stream.codec, "mpeg4");
+        assert_eq!((stream.width, stream.height), (Some(96), Some(64)));
+        assert_eq!(stream.fps, Some(25.0));
+        assert_eq!(stream.pixel_format.as_deref(), Some("yuv420p"));
+        assert_eq!(stream.sample_rate, None);
+    }
+
+    #[test]
+    fn probe_fails_for_missing_file() {
+        ffmpeg_next::init().unwrap();
+        assert!(probe(MediaSource::Url("/nonexistent/video.mkv".to_string())).is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use ffmpeg_next::format::Pixel;
+use ffmpeg_next::software::scaling::{Context, Flags};
+use ffmpeg_next::util::frame::Video;
+use skia_safe::{CubicResampler, FilterMode, MipmapMode, SamplingOptions};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScaleQuality {
+    FastBilinear,
+    Bilinear,
+    Bicubic,
+    /// Lanczos when frames are pre-scaled by swscale. Skia has no Lanczos sampler, so the
+    /// remaining draw-time scaling approximates it with a Catmull-Rom cubic
+    Lanczos,
+}
+
+impl ScaleQuality {
+    pub fn parse(value: &str) -> Option<Self> {
+        let quality = match value.trim().to_ascii_lowercase().as_str() {
+            "fast-bilinear" | "fast" => Self::FastBilinear,
+            "bilinear" => Self::Bilinear,
+            "bicubic" => Self::Bicubic,
+            "lanczos" => Self::Lanczos,
+            _ => return None,
+        };
+        Some(quality)
+    }
+
+    pub fn sws_flags(&self) -> Flags {
+        match self {
+            ScaleQuality::FastBilinear => Flags::FAST_BILINEAR,
+            ScaleQuality::Bilinear => Flags::BILINEAR,
+            ScaleQuality::Bicubic => Flags::BICUBIC,
+            ScaleQuality::Lanczos => Flags::LANCZOS,
+        }
+    }
+
+    pub fn sampling_options(&self) -> SamplingOptions {
+        match self {
+            ScaleQuality::FastBilinear => SamplingOptions::new(FilterMode::Linear, MipmapMode::None),
+            ScaleQuality::Bilinear => SamplingOptions::new(FilterMode::Linear, MipmapMode::Nearest),
+            ScaleQuality::Bicubic => SamplingOptions::from(CubicResampler::mitchell()),
+            // Closest to Lanczos among the samplers Skia offers
+            ScaleQuality::Lanczos => SamplingOptions::from(CubicResampler::catmull_rom()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScaleSettings {
+    /// Output size in device pixels, None means source resolution
+    pub target_size: Option<(u32, u32)>,
+    pub quality: ScaleQuality,
+}
+
+impl Default for ScaleSettings {
+    fn default() -> Self {
+        Self {
+            target_size: None,
+            quality: ScaleQuality::Bilinear,
+        }
+    }
+}
+
+impl ScaleSettings {
+    /// Output size for a frame, never upscaling beyond the source resolution
+    pub fn output_size(&self, width: u32, height: u32) -> (u32, u32) {
+        match self.target_size {
+            Some((w, h)) if w < width && h < height => (w.max(1), h.max(1)),
+            _ => (width, height),
+        }
+    }
+}
+
+pub fn create_rescale_context(frame: &Video, settings: &ScaleSettings) -> Context {
+    let (width, height) = settings.output_size(frame.width(), frame.height());
+    Context::get(
+        frame.format(),
+        frame.width(),
+        frame.height(),
+        Pixel::RGBA,
+        width,
+        height,
+        settings.quality.sws_flags(),
+    )
+    .unwrap()
+}
+
+/// Whether the context has to be rebuilt to convert `frame` with `settings`
+pub fn need_rebuild(context: Option<&Context>, frame: &Video, settings: &ScaleSettings) -> bool {
+    let Some(context) = context else {
+        return true;
+    };
+    let input = context.input();
+    let output = context.output();
+    let (width, height) = settings.output_size(frame.width(), frame.height());
+    input.format != frame.format()
+        || input.width != frame.width()
+        || input.height != frame.height()
+        || output.width != width
+        || output.height != height
+}
@@ -0,0 +1,113 @@
+use crate::source::MediaSource;
+use crate::subtitles::{burn_in, Cue};
+use anyhow::anyhow;
+use ffmpeg_next::format::Pixel;
+use ffmpeg_next::software::scaling::{Context, Flags};
+use ffmpeg_next::util::frame::Video;
+use libc::{memcpy, size_t};
+use skia_safe::{AlphaType, Bitmap, ColorSpace, ColorType, EncodedImageFormat, ImageInfo};
+use std::ffi::c_void;
+
+pub struct CaptureRequest {
+    pub path: String,
+    pub format: EncodedImageFormat,
+    /// 0-100, ignored by lossless formats
+    pub quality: u32,
+    /// Source to read the subtitle cue shown with the frame from, None to leave it out
+    pub subtitles: Option<MediaSource>,
+    pub on_done: Box<dyn FnOnce(Result<String, anyhow::Error>) + Send + 'static>,
+}
+
+pub fn parse_image_format(value: &str) -> Option<EncodedImageFormat> {
+    let format = match value.trim().to_ascii_lowercase().as_str() {
+        "png" => EncodedImageFormat::PNG,
+        "jpg" | "jpeg" => EncodedImageFormat::JPEG,
+        "webp" => EncodedImageFormat::WEBP,
+        _ => return None,
+    };
+    Some(format)
+}
+
+/// Encode a decoded frame at its source resolution with `cue` drawn onto it and write it to
+/// `path`
+pub fn save_frame(
+    frame: &Video,
+    cue: Option<&Cue>,
+    path: &str,
+    format: EncodedImageFormat,
+    quality: u32,
+) -> Result<(), anyhow::Error> {
+    let mut rescaler = Context::get(
+        frame.format(),
+        frame.width(),
+        frame.height(),
+        Pixel::RGBA,
+        frame.width(),
+        frame.height(),
+        Flags::BICUBIC,
+    )?;
+    let mut rgb_frame = Video::empty();
+    rescaler.run(frame, &mut rgb_frame)?;
+    let mut bitmap = load_bitmap_from_rgba_bytes(
+        rgb_frame.data(0),
+        rgb_frame.stride(0),
+        rgb_frame.width() as usize,
+        rgb_frame.height() as usize,
+        ColorType::RGBA8888,
+    );
+    if let Some(cue) = cue {
+        // Video frames have no transparency, which lets skia draw onto them
+        bitmap.set_alpha_type(AlphaType::Opaque);
+        burn_in(&bitmap, cue)?;
+    }
+    std::fs::write(path, encode_bitmap(&bitmap, format, quality)?)?;
+    Ok(())
+}
+
+pub fn encode_rgba(
+    data: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+    format: EncodedImageFormat,
+    quality: u32,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let bitmap = load_bitmap_from_rgba_bytes(data, stride, width, height, ColorType::RGBA8888);
+    encode_bitmap(&bitmap, format, quality)
+}
+
+fn encode_bitmap(
+    bitmap: &Bitmap,
+    format: EncodedImageFormat,
+    quality: u32,
+) -> Result<Vec<u8>, anyhow::Error> {
+    bitmap
+        .encode(format, quality)
+        .ok_or(anyhow!("failed to encode image as {:?}", format))
+}
+
+pub fn load_bitmap_from_rgba_bytes(
+    data: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+    color_type: ColorType,
+) -> Bitmap {
+    let width = width as i32;
+    let height = height as i32;
+    let image_info = ImageInfo::new(
+        (width, height),
+        color_type,
+        AlphaType::Unpremul,
+        ColorSpace::new_srgb(),
+    );
+    let mut bm = Bitmap::new();
+    let _ = bm.set_info(&image_info, stride);
+    bm.alloc_pixels();
+    unsafe {
+        let dest = bm.pixels();
+        let src = data.as_ptr() as *const c_void;
+        memcpy(dest, src, data.len() as size_t);
+    }
+    bm
+}
@@ -0,0 +1,396 @@
+use crate::interrupt::{interrupt_callback, Interrupted, Interrupter};
+use crate::live::{is_live_source, is_live_url, low_latency_options, LiveMode};
+use ffmpeg_next::ffi::{
+    av_free, av_malloc, avformat_alloc_context, avformat_close_input, avformat_find_stream_info,
+    avformat_open_input, avio_alloc_context, avio_context_free, AVIOContext,
+    AVIOInterruptCB, AVERROR, AVERROR_EOF, AVERROR_EXIT, AVFMT_FLAG_CUSTOM_IO,
+    AVIO_SEEKABLE_NORMAL, AVSEEK_SIZE,
+};
+use ffmpeg_next::format::context::Input;
+use ffmpeg_next::{decoder, frame, Dictionary, Packet};
+use std::ffi::{c_int, c_void, CString};
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::Arc;
+use std::time::Duration;
+
+const IO_BUFFER_SIZE: usize = 64 * 1024;
+
+pub trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// Where a player or extractor reads its media from
+pub enum MediaSource {
+    /// Path or URL opened by ffmpeg
+    Url(String),
+    /// Media held in memory, cheap to clone
+    Buffer(Arc<[u8]>),
+    /// Any reader, sources whose `seek` fails are played without seeking
+    Reader(Box<dyn ReadSeek>),
+}
+
+impl MediaSource {
+    /// Clone the source if it can be opened more than once
+    pub fn try_clone(&self) -> Option<MediaSource> {
+        match self {
+            MediaSource::Url(url) => Some(MediaSource::Url(url.clone())),
+            MediaSource::Buffer(data) => Some(MediaSource::Buffer(data.clone())),
+            MediaSource::Reader(_) => None,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            MediaSource::Url(url) => url.clone(),
+            MediaSource::Buffer(data) => format!("<buffer of {} bytes>", data.len()),
+            MediaSource::Reader(_) => "<reader>".to_string(),
+        }
+    }
+}
+
+/// Options applied when opening a source
+#[derive(Clone, Copy, Debug)]
+pub struct OpenOptions {
+    /// Limit for opening the source and probing its streams
+    pub open_timeout: Option<Duration>,
+    /// Limit for a single network read, passed to ffmpeg as `rw_timeout`
+    pub read_timeout: Option<Duration>,
+    pub live: LiveMode,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        Self {
+            open_timeout: Some(Duration::from_secs(30)),
+            read_timeout: Some(Duration::from_secs(15)),
+            live: LiveMode::Auto,
+        }
+    }
+}
+
+/// Opened format context, along with the custom IO context it reads from if any
+pub struct SourceInput {
+    // Declared first so the format context is closed before its IO and interrupter are freed
+    input: Input,
+    io: Option<CustomIo>,
+    interrupter: Interrupter,
+    /// Read over the network, where the downloaded range is worth reporting
+    remote: bool,
+    live: bool,
+}
+
+impl SourceInput {
+    pub fn open(
+        source: MediaSource,
+        options: OpenOptions,
+        interrupter: Interrupter,
+    ) -> Result<Self, anyhow::Error> {
+        let remote = matches!(&source, MediaSource::Url(url) if is_remote_url(url));
+        let live_url = matches!(&source, MediaSource::Url(url) if is_live_url(url));
+        let low_latency = match options.live {
+            LiveMode::Auto => live_url,
+            LiveMode::On => true,
+            LiveMode::Off => false,
+        };
+        let (url, io) = match source {
+            MediaSource::Url(url) => (Some(CString::new(url)?), None),
+            MediaSource::Buffer(data) => (None, Some(CustomIo::new(Box::new(Cursor::new(data))))),
+            MediaSource::Reader(reader) => (None, Some(CustomIo::new(reader))),
+        };
+        let input = unsafe { open_input(url, io.as_ref(), options, low_latency, &interrupter)? };
+        let live = match options.live {
+            LiveMode::Auto => is_live_source(live_url, input.format().name(), input.duration()),
+            LiveMode::On => true,
+            LiveMode::Off => false,
+        };
+        Ok(Self {
+            input,
+            io,
+            interrupter,
+            remote,
+            live,
+        })
+    }
+
+    /// Whether seeking is supported, false for forward-only readers and streams
+    pub fn is_seekable(&self) -> bool {
+        if self.live {
+            return false;
+        }
+        if let Some(io) = &self.io {
+            return io.seekable;
+        }
+        unsafe {
+            let pb = (*self.input.as_ptr()).pb;
+            // Formats without an IO context (e.g. RTSP) seek on their own
+            pb.is_null() || (*pb).seekable & AVIO_SEEKABLE_NORMAL as c_int != 0
+        }
+    }
+
+    /// Live streams have no duration and are presented with low latency
+    pub fn is_live(&self) -> bool {
+        self.live
+    }
+
+    /// Read the next packet, None at the end of the source. Fails with `Interrupted` when
+    /// a control request is waiting.
+    pub fn read_packet(&mut self) -> Result<Option<Packet>, anyhow::Error> {
+        loop {
+            let mut packet = Packet::empty();
+            let result = self.interrupter.monitor_read(|| packet.read(&mut self.input));
+            match result {
+                Ok(()) => return Ok(Some(packet)),
+                Err(ffmpeg_next::Error::Eof) => return Ok(None),
+                // Some demuxers ask to retry while data is not ready yet
+                Err(ffmpeg_next::Error::Other { errno: libc::EAGAIN }) => {}
+                Err(e) => return Err(self.interrupter.map_error(e)),
+            }
+        }
+    }
+
+    /// Read over the network, where the downloaded range is worth reporting
+    pub fn is_remote(&self) -> bool {
+        self.remote
+    }
+
+    /// Decode up to the first frame of `stream_index`, draining the decoder at the end of
+    /// the source
+    pub fn decode_next_frame(
+        &mut self,
+        decoder: &mut decoder::Video,
+        stream_index: usize,
+    ) -> Result<frame::Video, anyhow::Error> {
+        let mut decoded = frame::Video::empty();
+        while let Some(packet) = self.read_packet()? {
+            if packet.stream() != stream_index {
+                continue;
+            }
+            decoder.send_packet(&packet)?;
+            if decoder.receive_frame(&mut decoded).is_ok() {
+                return Ok(decoded);
+            }
+        }
+        decoder.send_eof()?;
+        decoder.receive_frame(&mut decoded)?;
+        Ok(decoded)
+    }
+}
+
+impl Deref for SourceInput {
+    type Target = Input;
+
+    fn deref(&self) -> &Self::Target {
+        &self.input
+    }
+}
+
+impl DerefMut for SourceInput {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.input
+    }
+}
+
+fn is_remote_url(url: &str) -> bool {
+    match url.split_once("://") {
+        Some((scheme, _)) => !scheme.eq_ignore_ascii_case("file"),
+        None => false,
+    }
+}
+
+/// AVIOContext reading from a boxed reader
+struct CustomIo {
+    context: *mut AVIOContext,
+    reader: *mut Box<dyn ReadSeek>,
+    seekable: bool,
+}
+
+// The reader is Send and the context is only used by the thread owning the input
+unsafe impl Send for CustomIo {}
+
+impl CustomIo {
+    fn new(mut reader: Box<dyn ReadSeek>) -> Self {
+        let seekable = reader.stream_position().is_ok();
+        let reader = Box::into_raw(Box::new(reader));
+        unsafe {
+            let buffer = av_malloc(IO_BUFFER_SIZE) as *mut u8;
+            let context = avio_alloc_context(
+                buffer,
+                IO_BUFFER_SIZE as c_int,
+                0,
+                reader as *mut c_void,
+                Some(read_packet),
+                None,
+                if seekable { Some(seek) } else { None },
+            );
+            if !seekable {
+                (*context).seekable = 0;
+            }
+            Self {
+                context,
+                reader,
+                seekable,
+            }
+        }
+    }
+}
+
+impl Drop for CustomIo {
+    fn drop(&mut self) {
+        unsafe {
+            // The buffer may have been reallocated by ffmpeg, free the current one
+            av_free((*self.context).buffer as *mut c_void);
+            avio_context_free(&mut self.context);
+            drop(Box::from_raw(self.reader));
+        }
+    }
+}
+
+/// Open `url`, or the custom `io` when given, with `interrupter` attached
+unsafe fn open_input(
+    url: Option<CString>,
+    io: Option<&CustomIo>,
+    options: OpenOptions,
+    low_latency: bool,
+    interrupter: &Interrupter,
+) -> Result<Input, anyhow::Error> {
+    let mut format_context = avformat_alloc_context();
+    (*format_context).interrupt_callback = AVIOInterruptCB {
+        callback: Some(interrupt_callback),
+        opaque: interrupter.as_opaque(),
+    };
+    if let Some(io) = io {
+        (*format_context).pb = io.context;
+        (*format_context).flags |= AVFMT_FLAG_CUSTOM_IO as c_int;
+    }
+    let mut dictionary = Dictionary::new();
+    if let Some(timeout) = options.read_timeout {
+        dictionary.set("rw_timeout", &timeout.as_micros().to_string());
+    }
+    if low_latency {
+        for (key, value) in low_latency_options() {
+            dictionary.set(key, value);
+        }
+    }
+    let mut format_options = dictionary.disown();
+    let url = url.as_ref().map_or(ptr::null(), |url| url.as_ptr());
+    let ret = interrupter.with_timeout(options.open_timeout, || {
+        // Frees the format context on failure
+        let ret = avformat_open_input(&mut format_context, url, ptr::null(), &mut format_options);
+        if ret < 0 {
+            return ret;
+        }
+        let ret = avformat_find_stream_info(format_context, ptr::null_mut());
+        if ret < 0 {
+            avformat_close_input(&mut format_context);
+        }
+        ret
+    });
+    // Options not consumed by the demuxer
+    drop(Dictionary::own(format_options));
+    if ret < 0 {
+        return Err(interrupter.map_error(ffmpeg_next::Error::from(ret)));
+    }
+    Ok(Input::wrap(format_context))
+}
+
+unsafe extern "C" fn read_packet(opaque: *mut c_void, buf: *mut u8, buf_size: c_int) -> c_int {
+    let reader = &mut *(opaque as *mut Box<dyn ReadSeek>);
+    let buf = std::slice::from_raw_parts_mut(buf, buf_size as usize);
+    match reader.read(buf) {
+        Ok(0) => AVERROR_EOF,
+        Ok(n) => n as c_int,
+        // Mapped to `Interrupted` by the interrupter, like aborted network reads
+        Err(e) if e.get_ref().map_or(false, |e| e.is::<Interrupted>()) => AVERROR_EXIT,
+        Err(e) => {
+            println!("source read error: {:?}", e);
+            AVERROR(libc::EIO)
+        }
+    }
+}
+
+unsafe extern "C" fn seek(opaque: *mut c_void, offset: i64, whence: c_int) -> i64 {
+    let reader = &mut *(opaque as *mut Box<dyn ReadSeek>);
+    let result = if whence & AVSEEK_SIZE as c_int != 0 {
+        stream_len(reader.as_mut())
+    } else {
+        let pos = match whence & !(ffmpeg_next::ffi::AVSEEK_FORCE as c_int) {
+            libc::SEEK_SET => SeekFrom::Start(offset as u64),
+            libc::SEEK_CUR => SeekFrom::Current(offset),
+            libc::SEEK_END => SeekFrom::End(offset),
+            _ => return AVERROR(libc::EINVAL) as i64,
+        };
+        reader.seek(pos)
+    };
+    match result {
+        Ok(pos) => pos as i64,
+        Err(_) => AVERROR(libc::EIO) as i64,
+    }
+}
+
+fn stream_len(reader: &mut dyn ReadSeek) -> std::io::Result<u64> {
+    let pos = reader.stream_position()?;
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(pos))?;
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_media::{temp_dir, TestVideo};
+    use std::io::ErrorKind;
+
+    /// Reader that cannot seek, like a pipe
+    struct ForwardOnly(Cursor<Vec<u8>>);
+
+    impl Read for ForwardOnly {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Seek for ForwardOnly {
+        fn seek(&mut self, _pos: SeekFrom) -> std::io::Result<u64> {
+            Err(ErrorKind::Unsupported.into())
+        }
+    }
+
+    #[test]
+    fn unknown_duration_is_not_live() {
+        let dir = temp_dir("forward-only");
+        let path = dir.join("video.ts");
+        TestVideo::default().write(&path, None, &[]);
+        let data = std::fs::read(&path).unwrap();
+        let input = SourceInput::open(
+            MediaSource::Reader(Box::new(ForwardOnly(Cursor::new(data)))),
+            OpenOptions::default(),
+            Interrupter::default(),
+        )
+        .unwrap();
+        assert!(!input.is_live());
+        assert!(!input.is_seekable());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn live_mode_overrides_detection() {
+        let dir = temp_dir("live-mode");
+        let path = dir.join("video.mkv");
+        TestVideo::default().write(&path, None, &[]);
+        let url = path.to_str().unwrap().to_string();
+        let open = |live| {
+            let options = OpenOptions {
+                live,
+                ..OpenOptions::default()
+            };
+            SourceInput::open(MediaSource::Url(url.clone()), options, Interrupter::default())
+                .unwrap()
+        };
+        assert!(!open(LiveMode::Auto).is_live());
+        assert!(open(LiveMode::On).is_live());
+        assert!(!open(LiveMode::On).is_seekable());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
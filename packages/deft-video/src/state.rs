@@ -0,0 +1,164 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaybackState {
+    Idle,
+    Loading,
+    Paused,
+    Playing,
+    Seeking,
+    Ended,
+    Error,
+}
+
+impl PlaybackState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PlaybackState::Idle => "idle",
+            PlaybackState::Loading => "loading",
+            PlaybackState::Paused => "paused",
+            PlaybackState::Playing => "playing",
+            PlaybackState::Seeking => "seeking",
+            PlaybackState::Ended => "ended",
+            PlaybackState::Error => "error",
+        }
+    }
+}
+
+/// Authoritative playback state of a video element, updated from the player thread
+#[derive(Clone, Debug)]
+pub struct PlaybackStatus {
+    pub state: PlaybackState,
+    pub current_time: f32,
+    pub duration: f32,
+    /// Time ranges (start, end) available without further loading
+    pub buffered: Vec<(f32, f32)>,
+    pub volume: f32,
+    /// Playback speed, 1 for normal speed
+    pub rate: f32,
+    pub error: Option<String>,
+    /// State to restore when a seek lands
+    state_before_seek: PlaybackState,
+}
+
+impl Default for PlaybackStatus {
+    fn default() -> Self {
+        Self {
+            state: PlaybackState::Idle,
+            current_time: 0.0,
+            duration: 0.0,
+            buffered: Vec::new(),
+            volume: 1.0,
+            rate: 1.0,
+            error: None,
+            state_before_seek: PlaybackState::Paused,
+        }
+    }
+}
+
+impl PlaybackStatus {
+    pub fn reset(&mut self, state: PlaybackState) {
+        *self = Self {
+            state,
+            volume: self.volume,
+            rate: self.rate,
+            ..Self::default()
+        };
+    }
+
+    pub fn start_seeking(&mut self) {
+        if self.state != PlaybackState::Seeking {
+            self.state_before_seek = match self.state {
+                PlaybackState::Playing => PlaybackState::Playing,
+                _ => PlaybackState::Paused,
+            };
+            self.state = PlaybackState::Seeking;
+        }
+    }
+
+    /// Playing or paused, a pending seek lands in this state
+    pub fn set_playing(&mut self, playing: bool) {
+        let state = if playing {
+            PlaybackState::Playing
+        } else {
+            PlaybackState::Paused
+        };
+        if self.state == PlaybackState::Seeking {
+            self.state_before_seek = state;
+        } else {
+            self.state = state;
+        }
+    }
+
+    pub fn update_time(&mut self, time: f32) {
+        self.current_time = time;
+        if self.state == PlaybackState::Seeking {
+            self.state = self.state_before_seek;
+        }
+    }
+
+    pub fn set_error(&mut self, error: String) {
+        self.state = PlaybackState::Error;
+        self.error = Some(error);
+    }
+}
+
+/// Output volume shared with the audio thread, stored as f32 bits
+#[derive(Clone)]
+pub struct SharedVolume(Arc<AtomicU32>);
+
+impl Default for SharedVolume {
+    fn default() -> Self {
+        Self(Arc::new(AtomicU32::new(1.0f32.to_bits())))
+    }
+}
+
+impl SharedVolume {
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, volume: f32) {
+        self.0.store(volume.to_bits(), Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seek_lands_in_requested_state() {
+        let mut status = PlaybackStatus::default();
+        status.set_playing(true);
+        status.start_seeking();
+        assert_eq!(status.state, PlaybackState::Seeking);
+        status.set_playing(false);
+        status.update_time(3.0);
+        assert_eq!(status.state, PlaybackState::Paused);
+    }
+
+    #[test]
+    fn seek_after_end_lands_paused() {
+        let mut status = PlaybackStatus::default();
+        status.state = PlaybackState::Ended;
+        status.start_seeking();
+        status.update_time(1.0);
+        assert_eq!(status.state, PlaybackState::Paused);
+    }
+
+    #[test]
+    fn reset_keeps_volume_and_rate() {
+        let mut status = PlaybackStatus {
+            volume: 0.5,
+            rate: 2.0,
+            ..PlaybackStatus::default()
+        };
+        status.set_error("lost".to_string());
+        status.reset(PlaybackState::Loading);
+        assert_eq!(status.state, PlaybackState::Loading);
+        assert_eq!((status.volume, status.rate), (0.5, 2.0));
+        assert_eq!(status.error, None);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::export::seek_input;
+use crate::interrupt::Interrupter;
+use crate::snapshot::load_bitmap_from_rgba_bytes;
+use crate::source::{MediaSource, OpenOptions, SourceInput};
+use anyhow::anyhow;
+use ffmpeg_next::codec::subtitle::Rect as SubtitleRect;
+use ffmpeg_next::{codec, media, Subtitle};
+use skia_safe::{
+    Bitmap, Canvas, Color, ColorType, Font, FontMgr, FontStyle, Paint, PaintStyle, Rect,
+};
+
+/// Seconds before the frame to start reading from, cues are shown for a few seconds at most
+const LOOKBEHIND: f64 = 20.0;
+/// Seconds of packets past the frame after which no earlier cue is expected, covers the
+/// interleaving of the streams
+const LOOKAHEAD: f64 = 2.0;
+
+/// Subtitle shown at a given time
+#[derive(Debug, Default)]
+pub struct Cue {
+    /// Lines of text cues, top to bottom
+    pub lines: Vec<String>,
+    /// Pictures of bitmap cues, e.g. DVD or Blu-ray subtitles
+    pub images: Vec<CueImage>,
+    /// Size of the video the image positions refer to, None for the frame size
+    pub canvas_size: Option<(u32, u32)>,
+}
+
+#[derive(Debug)]
+pub struct CueImage {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+/// Cue of the first subtitle stream of `source` shown at `time` in seconds, None if there is
+/// none or the source has no subtitles
+pub fn active_cue(
+    source: MediaSource,
+    time: f64,
+    interrupter: Interrupter,
+) -> Result<Option<Cue>, anyhow::Error> {
+    let mut input = SourceInput::open(source, OpenOptions::default(), interrupter)?;
+    let Some(stream) = input.streams().best(media::Type::Subtitle) else {
+        return Ok(None);
+    };
+    let stream_index = stream.index();
+    let time_base = f64::from(stream.time_base());
+    let parameters = stream.parameters();
+    // Bitmap cues are positioned on a canvas of this size, e.g. 1920x1080 for Blu-ray
+    let canvas_size = unsafe {
+        let codecpar = parameters.as_ptr();
+        Some(((*codecpar).width as u32, (*codecpar).height as u32))
+            .filter(|&(width, height)| width > 0 && height > 0)
+    };
+    let mut decoder = codec::Context::from_parameters(parameters)?
+        .decoder()
+        .subtitle()?;
+    seek_input(&mut input, (time - LOOKBEHIND).max(0.0))?;
+
+    // Latest cue started at or before `time` and when it ends, None until the next cue
+    let mut current: Option<(Cue, Option<f64>)> = None;
+    while let Some(packet) = input.read_packet()? {
+        let packet_time_base = f64::from(input.stream(packet.stream()).unwrap().time_base());
+        let Some(start) = packet.pts().or(packet.dts()).map(|ts| ts as f64 * packet_time_base)
+        else {
+            continue;
+        };
+        if start > time + LOOKAHEAD {
+            break;
+        }
+        if packet.stream() != stream_index {
+            continue;
+        }
+        let mut subtitle = Subtitle::new();
+        if !decoder.decode(&packet, &mut subtitle)? {
+            continue;
+        }
+        let shown = start + subtitle.start() as f64 / 1000.0;
+        if shown > time {
+            break;
+        }
+        let end = match subtitle.end() {
+            0 | u32::MAX if packet.duration() > 0 => {
+                Some(start + packet.duration() as f64 * time_base)
+            }
+            0 | u32::MAX => None,
+            end => Some(start + end as f64 / 1000.0),
+        };
+        // An empty cue clears the previous one
+        current = Some((read_cue(&subtitle, canvas_size), end));
+    }
+    Ok(current
+        .filter(|(_, end)| end.map_or(true, |end| time < end))
+        .map(|(cue, _)| cue)
+        .filter(|cue| !cue.lines.is_empty() || !cue.images.is_empty()))
+}
+
+fn read_cue(subtitle: &Subtitle, canvas_size: Option<(u32, u32)>) -> Cue {
+    let mut cue = Cue {
+        canvas_size,
+        ..Cue::default()
+    };
+    for rect in subtitle.rects() {
+        match &rect {
+            SubtitleRect::Text(text) => cue.lines.extend(text_lines(text.get())),
+            SubtitleRect::Ass(ass) => cue.lines.extend(text_lines(&ass_text(ass.get()))),
+            SubtitleRect::Bitmap(_) => cue.images.extend(unsafe { bitmap_image(&rect) }),
+            SubtitleRect::None(_) => {}
+        }
+    }
+    cue
+}
+
+/// Text of an ASS dialogue event `ReadOrder,Layer,Style,Name,MarginL,MarginR,MarginV,Effect,
+/// Text` without its override blocks, with line breaks as `\n`
+fn ass_text(event: &str) -> String {
+    let text = event.splitn(9, ',').nth(8).unwrap_or(event);
+    let mut plain = String::new();
+    let mut in_block = false;
+    for c in text.chars() {
+        match c {
+            '{' => in_block = true,
+            '}' if in_block => in_block = false,
+            _ if !in_block => plain.push(c),
+            _ => {}
+        }
+    }
+    plain
+        .replace("\\N", "\n")
+        .replace("\\n", "\n")
+        .replace("\\h", " ")
+}
+
+fn text_lines(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+}
+
+/// Palette image of a bitmap cue as RGBA
+unsafe fn bitmap_image(rect: &SubtitleRect) -> Option<CueImage> {
+    let rect = &*rect.as_ptr();
+    let (width, height) = (rect.w as usize, rect.h as usize);
+    if width == 0 || height == 0 || rect.data[0].is_null() || rect.data[1].is_null() {
+        return None;
+    }
+    // Colors are native endian ARGB
+    let colors = rect.nb_colors as usize;
+    let palette = std::slice::from_raw_parts(rect.data[1] as *const u32, colors);
+    let stride = rect.linesize[0] as usize;
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        let row = std::slice::from_raw_parts(rect.data[0].add(y * stride), width);
+        for &index in row {
+            let argb = palette.get(index as usize).copied().unwrap_or(0);
+            rgba.extend([(argb >> 16) as u8, (argb >> 8) as u8, argb as u8, (argb >> 24) as u8]);
+        }
+    }
+    Some(CueImage {
+        x: rect.x.max(0) as usize,
+        y: rect.y.max(0) as usize,
+        width,
+        height,
+        rgba,
+    })
+}
+
+/// Draw `cue` onto a frame: images at their position scaled to the frame, text centred at
+/// the bottom, white with a black outline
+pub fn burn_in(frame: &Bitmap, cue: &Cue) -> Result<(), anyhow::Error> {
+    let canvas = Canvas::from_bitmap(frame, None).ok_or(anyhow!("cannot draw onto the frame"))?;
+    let (width, height) = (frame.width() as f32, frame.height() as f32);
+    let (scale_x, scale_y) = match cue.canvas_size {
+        Some((w, h)) => (width / w as f32, height / h as f32),
+        None => (1.0, 1.0),
+    };
+    for image in &cue.images {
+        let bitmap = load_bitmap_from_rgba_bytes(
+            &image.rgba,
+            image.width * 4,
+            image.width,
+            image.height,
+            ColorType::RGBA8888,
+        );
+        let dest = Rect::from_xywh(
+            image.x as f32 * scale_x,
+            image.y as f32 * scale_y,
+            image.width as f32 * scale_x,
+            image.height as f32 * scale_y,
+        );
+        canvas.draw_image_rect(bitmap.as_image(), None, dest, &Paint::default());
+    }
+    if cue.lines.is_empty() {
+        return Ok(());
+    }
+    let typeface = FontMgr::new()
+        .legacy_make_typeface(None, FontStyle::normal())
+        .ok_or(anyhow!("no font available to draw subtitles"))?;
+    let font = Font::from_typeface(typeface, height / 16.0);
+    let line_height = font.spacing();
+    let mut outline = Paint::default();
+    outline.set_anti_alias(true);
+    outline.set_color(Color::BLACK);
+    outline.set_style(PaintStyle::Stroke);
+    outline.set_stroke_width(font.size() / 8.0);
+    let mut fill = Paint::default();
+    fill.set_anti_alias(true);
+    fill.set_color(Color::WHITE);
+    // Baseline of the last line a twentieth of the height above the bottom
+    let mut baseline = height - height / 20.0 - line_height * (cue.lines.len() - 1) as f32;
+    for line in &cue.lines {
+        let (line_width, _) = font.measure_str(line, Some(&fill));
+        let origin = ((width - line_width) / 2.0, baseline);
+        canvas.draw_str(line, origin, &font, &outline);
+        canvas.draw_str(line, origin, &font, &fill);
+        baseline += line_height;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_media::temp_dir;
+
+    #[test]
+    fn ass_override_blocks_are_removed() {
+        let event = "0,0,Default,,0,0,0,,{\\i1}Hello,{\\b1} world{\\b0}\\Nsecond line";
+        assert_eq!(ass_text(event), "Hello, world\nsecond line");
+    }
+
+    #[test]
+    fn cue_is_found_at_its_time() {
+        ffmpeg_next::init().unwrap();
+        let dir = temp_dir("subtitles");
+        let path = dir.join("cues.srt");
+        std::fs::write(
+            &path,
+            "1\n00:00:01,000 --> 00:00:02,500\nFirst cue\n\n\
+             2\n00:00:04,000 --> 00:00:05,000\nSecond\n<i>two lines</i>\n\n",
+        )
+        .unwrap();
+        let cue_at = |time| {
+            let source = MediaSource::Url(path.to_str().unwrap().to_string());
+            active_cue(source, time, Interrupter::default())
+                .unwrap()
+                .map(|cue| cue.lines)
+        };
+        assert_eq!(cue_at(0.5), None);
+        assert_eq!(cue_at(1.5), Some(vec!["First cue".to_string()]));
+        assert_eq!(cue_at(3.0), None);
+        assert_eq!(
+            cue_at(4.2),
+            Some(vec!["Second".to_string(), "two lines".to_string()])
+        );
+    }
+}
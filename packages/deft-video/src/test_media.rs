@@ -0,0 +1,223 @@
+//! Media and servers generated by the tests, so that no fixtures need to be checked in
+
+use ffmpeg_next::{codec, decoder, encoder, format, frame, media, Dictionary, Packet, Rational};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+pub struct TestVideo {
+    pub width: u32,
+    pub height: u32,
+    pub fps: i32,
+    pub frames: usize,
+    /// Frames between keyframes
+    pub gop: u32,
+    /// Consecutive B-frames, decoded after the frame shown after them
+    pub b_frames: usize,
+}
+
+impl Default for TestVideo {
+    fn default() -> Self {
+        Self {
+            width: 64,
+            height: 48,
+            fps: 25,
+            frames: 50,
+            gop: 25,
+            b_frames: 0,
+        }
+    }
+}
+
+impl TestVideo {
+    /// Brightness of frame `index`, changing every frame so that frames can be told apart
+    pub fn luma(index: usize) -> u8 {
+        (index * 7 % 200 + 16) as u8
+    }
+
+    /// Index among `candidates` of the frame whose brightness is closest to `frame`'s, which
+    /// must be YUV
+    pub fn frame_index(frame: &frame::Video, candidates: std::ops::Range<usize>) -> usize {
+        let luma = frame.data(0)[0] as i32;
+        candidates
+            .min_by_key(|&index| (Self::luma(index) as i32 - luma).abs())
+            .unwrap()
+    }
+
+    /// Encode MPEG-4 video into `path`, with the muxer `format` or the one guessed from the
+    /// extension. `options` are passed to the muxer, e.g. for HLS segmenting
+    pub fn write(&self, path: &Path, format: Option<&str>, options: &[(&str, &str)]) {
+        ffmpeg_next::init().unwrap();
+        let mut octx = match format {
+            Some(format) => format::output_as(&path, format).unwrap(),
+            None => format::output(&path).unwrap(),
+        };
+        let codec = encoder::find(codec::Id::MPEG4).unwrap();
+        let time_base = Rational::new(1, self.fps);
+        let mut context = codec::context::Context::new_with_codec(codec)
+            .encoder()
+            .video()
+            .unwrap();
+        context.set_width(self.width);
+        context.set_height(self.height);
+        context.set_format(format::Pixel::YUV420P);
+        context.set_time_base(time_base);
+        context.set_frame_rate(Some(Rational::new(self.fps, 1)));
+        context.set_gop(self.gop);
+        context.set_max_b_frames(self.b_frames);
+        if octx
+            .format()
+            .flags()
+            .contains(format::flag::Flags::GLOBAL_HEADER)
+        {
+            context.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+        let mut encoder = context.open_as(codec).unwrap();
+        let mut ost = octx.add_stream(codec).unwrap();
+        ost.set_parameters(&encoder);
+        ost.set_time_base(time_base);
+        let mut muxer_options = Dictionary::new();
+        for (key, value) in options {
+            muxer_options.set(key, value);
+        }
+        octx.write_header_with(muxer_options).unwrap();
+
+        let mut picture = frame::Video::new(format::Pixel::YUV420P, self.width, self.height);
+        for index in 0..self.frames {
+            picture.data_mut(0).fill(Self::luma(index));
+            picture.data_mut(1).fill(128);
+            picture.data_mut(2).fill(128);
+            picture.set_pts(Some(index as i64));
+            encoder.send_frame(&picture).unwrap();
+            write_packets(&mut encoder, time_base, &mut octx);
+        }
+        encoder.send_eof().unwrap();
+        write_packets(&mut encoder, time_base, &mut octx);
+        octx.write_trailer().unwrap();
+    }
+}
+
+fn write_packets(
+    encoder: &mut encoder::video::Encoder,
+    time_base: Rational,
+    octx: &mut format::context::Output,
+) {
+    let output_time_base = octx.stream(0).unwrap().time_base();
+    let mut packet = Packet::empty();
+    while encoder.receive_packet(&mut packet).is_ok() {
+        packet.set_stream(0);
+        packet.rescale_ts(time_base, output_time_base);
+        packet.write_interleaved(octx).unwrap();
+    }
+}
+
+/// Video frames of the file at `path` in presentation order, with their time in seconds
+pub fn decode_video(path: &Path) -> Vec<(f64, frame::Video)> {
+    let mut input = format::input(&path).unwrap();
+    let stream = input.streams().best(media::Type::Video).unwrap();
+    let index = stream.index();
+    let time_base = f64::from(stream.time_base());
+    let mut decoder: decoder::Video = codec::Context::from_parameters(stream.parameters())
+        .unwrap()
+        .decoder()
+        .video()
+        .unwrap();
+    let mut frames = Vec::new();
+    let mut receive = |decoder: &mut decoder::Video| {
+        let mut frame = frame::Video::empty();
+        while decoder.receive_frame(&mut frame).is_ok() {
+            let time = frame.pts().unwrap_or(0) as f64 * time_base;
+            frames.push((time, std::mem::replace(&mut frame, frame::Video::empty())));
+        }
+    };
+    for (stream, packet) in input.packets() {
+        if stream.index() == index {
+            decoder.send_packet(&packet).unwrap();
+            receive(&mut decoder);
+        }
+    }
+    decoder.send_eof().unwrap();
+    receive(&mut decoder);
+    frames.sort_by(|a, b| a.0.total_cmp(&b.0));
+    frames
+}
+
+/// Empty directory below the system temp dir, unique per test
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("deft-video-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// HTTP/1.0 server for the files of a directory, one thread per connection
+pub struct TestServer {
+    pub port: u16,
+    /// Requests served so far
+    pub requests: Arc<AtomicUsize>,
+    /// Responses stall without closing after this many bytes of a body, usize::MAX for none
+    pub stall_after: Arc<AtomicUsize>,
+}
+
+impl TestServer {
+    pub fn start(root: &Path) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let stall_after = Arc::new(AtomicUsize::new(usize::MAX));
+        let root = root.to_path_buf();
+        {
+            let requests = requests.clone();
+            let stall_after = stall_after.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let root = root.clone();
+                    let stall_after = stall_after.load(Ordering::SeqCst);
+                    requests.fetch_add(1, Ordering::SeqCst);
+                    thread::spawn(move || serve(stream, &root, stall_after));
+                }
+            });
+        }
+        Self {
+            port,
+            requests,
+            stall_after,
+        }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}/{}", self.port, path)
+    }
+}
+
+fn serve(mut stream: TcpStream, root: &Path, stall_after: usize) {
+    let mut request_line = String::new();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    // Skip the headers, ranges are not supported so the whole file is sent
+    let mut line = String::new();
+    while reader.read_line(&mut line).map_or(false, |n| n > 2) {
+        line.clear();
+    }
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let path = path.split('?').next().unwrap().trim_start_matches('/');
+    let Ok(body) = std::fs::read(root.join(path)) else {
+        let _ = stream.write_all(b"HTTP/1.0 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+        return;
+    };
+    let header = format!(
+        "HTTP/1.0 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    let _ = stream.write_all(header.as_bytes());
+    let _ = stream.write_all(&body[..body.len().min(stall_after)]);
+    if stall_after < body.len() {
+        // Keep the connection open so that the client runs into its read timeout
+        thread::sleep(std::time::Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,207 @@
+use crate::snapshot::{encode_rgba, parse_image_format};
+use crate::interrupt::Interrupter;
+use crate::source::{MediaSource, OpenOptions, SourceInput};
+use anyhow::anyhow;
+use ffmpeg_next::ffi::AV_TIME_BASE;
+use ffmpeg_next::format::Pixel;
+use ffmpeg_next::software::scaling::{Context, Flags};
+use ffmpeg_next::{frame, Rational};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+pub struct ThumbnailParams {
+    pub src: MediaSource,
+    /// Sprite sheet output path, the image format is taken from the extension
+    pub output: String,
+    pub count: usize,
+    pub tile_width: u32,
+    pub columns: usize,
+    pub on_progress: Box<dyn FnMut(f32) + Send + 'static>,
+    pub on_done: Box<dyn FnOnce(Result<ThumbnailSheet, anyhow::Error>) + Send + 'static>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Thumbnail {
+    pub time: f32,
+    pub x: u32,
+    pub y: u32,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ThumbnailSheet {
+    pub path: String,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub columns: usize,
+    pub thumbnails: Vec<Thumbnail>,
+}
+
+/// Extracts evenly spaced keyframes of a source into a sprite sheet on a background thread.
+/// Dropping the extractor cancels it and waits for the thread to exit.
+pub struct ThumbnailExtractor {
+    cancelled: Arc<AtomicBool>,
+    /// Aborts an open or read blocked on a stalled network source
+    interrupter: Interrupter,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ThumbnailExtractor {
+    pub fn start(params: ThumbnailParams) -> Self {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let thread_cancelled = cancelled.clone();
+        let interrupter = Interrupter::default();
+        let thread_interrupter = interrupter.clone();
+        let thread = thread::spawn(move || {
+            let ThumbnailParams {
+                src,
+                output,
+                count,
+                tile_width,
+                columns,
+                mut on_progress,
+                on_done,
+            } = params;
+            let result = extract_sprite_sheet(
+                src,
+                &output,
+                count,
+                tile_width,
+                columns,
+                &mut on_progress,
+                thread_interrupter,
+            );
+            if !thread_cancelled.load(Ordering::Relaxed) {
+                on_done(result);
+            }
+        });
+        Self {
+            cancelled,
+            interrupter,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn cancel(&self) {
+        if !self.cancelled.swap(true, Ordering::Relaxed) {
+            // Never acknowledged, so every further IO of the extractor fails right away
+            self.interrupter.request();
+        }
+    }
+}
+
+impl Drop for ThumbnailExtractor {
+    fn drop(&mut self) {
+        self.cancel();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn extract_sprite_sheet(
+    src: MediaSource,
+    output: &str,
+    count: usize,
+    tile_width: u32,
+    columns: usize,
+    on_progress: &mut dyn FnMut(f32),
+    interrupter: Interrupter,
+) -> Result<ThumbnailSheet, anyhow::Error> {
+    let extension = Path::new(output)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("");
+    let format = parse_image_format(extension)
+        .ok_or(anyhow!("unsupported sprite sheet format: {}", output))?;
+    let count = count.max(1);
+    let columns = columns.clamp(1, count);
+    let rows = count.div_ceil(columns);
+
+    let mut input = SourceInput::open(src, OpenOptions::default(), interrupter.clone())?;
+    // Evenly spaced seek times need the duration, unknown e.g. for streams
+    if input.duration() <= 0 || !input.is_seekable() {
+        return Err(anyhow!("thumbnails need a seekable source with a known duration"));
+    }
+    let video_stream = input
+        .streams()
+        .best(ffmpeg_next::media::Type::Video)
+        .ok_or(anyhow!("no video stream"))?;
+    let stream_index = video_stream.index();
+    let time_base = video_stream.time_base();
+    let mut decoder = ffmpeg_next::codec::Context::from_parameters(video_stream.parameters())?
+        .decoder()
+        .video()?;
+    let tile_width = tile_width.max(2) & !1;
+    // Tiles have the display aspect ratio, anamorphic sources have non-square samples
+    let sample_aspect = match decoder.aspect_ratio() {
+        r if r.numerator() > 0 && r.denominator() > 0 => f64::from(r),
+        _ => 1.0,
+    };
+    let display_width = decoder.width().max(1) as f64 * sample_aspect;
+    let tile_height =
+        ((decoder.height() as f64 * tile_width as f64 / display_width).round() as u32).max(2) & !1;
+    let duration = input.duration() as f64 / AV_TIME_BASE as f64;
+
+    let sheet_width = tile_width as usize * columns;
+    let sheet_height = tile_height as usize * rows;
+    let sheet_stride = sheet_width * 4;
+    let mut sheet = vec![0u8; sheet_stride * sheet_height];
+    let mut thumbnails = Vec::with_capacity(count);
+    let mut scaled = frame::Video::empty();
+    for i in 0..count {
+        // Cancelling requests an interrupt that is never acknowledged
+        if interrupter.has_pending() {
+            return Err(anyhow!("cancelled"));
+        }
+        let time = duration * (i as f64 + 0.5) / count as f64;
+        let ts = (time * AV_TIME_BASE as f64) as i64;
+        input.seek(ts, ..ts)?;
+        decoder.flush();
+        let decoded = input.decode_next_frame(&mut decoder, stream_index)?;
+        let mut scaler = Context::get(
+            decoded.format(),
+            decoded.width(),
+            decoded.height(),
+            Pixel::RGBA,
+            tile_width,
+            tile_height,
+            Flags::AREA,
+        )?;
+        scaler.run(&decoded, &mut scaled)?;
+
+        let x = (i % columns) as u32 * tile_width;
+        let y = (i / columns) as u32 * tile_height;
+        let row_bytes = tile_width as usize * 4;
+        for row in 0..tile_height as usize {
+            let src_offset = row * scaled.stride(0);
+            let dest_offset = (y as usize + row) * sheet_stride + x as usize * 4;
+            sheet[dest_offset..dest_offset + row_bytes]
+                .copy_from_slice(&scaled.data(0)[src_offset..src_offset + row_bytes]);
+        }
+        thumbnails.push(Thumbnail {
+            time: frame_time(&decoded, time_base).unwrap_or(time as f32),
+            x,
+            y,
+        });
+        on_progress((i + 1) as f32 / count as f32);
+    }
+
+    let data = encode_rgba(&sheet, sheet_stride, sheet_width, sheet_height, format, 85)?;
+    std::fs::write(output, data)?;
+    Ok(ThumbnailSheet {
+        path: output.to_string(),
+        tile_width,
+        tile_height,
+        columns,
+        thumbnails,
+    })
+}
+
+fn frame_time(frame: &frame::Video, time_base: Rational) -> Option<f32> {
+    frame
+        .pts()
+        .map(|pts| (pts as f64 * f64::from(time_base)) as f32)
+}
@@ -0,0 +1,168 @@
+use ffmpeg_next::ffi::{av_dict_get, avcodec_get_name, AVDiscard, AVMediaType, AVProgram};
+use ffmpeg_next::format::context::Input;
+use serde::Serialize;
+use std::ffi::{c_char, CStr};
+use std::ptr;
+use std::time::{Duration, Instant};
+
+/// How often the automatic selection reconsiders the variant
+const ABR_INTERVAL: Duration = Duration::from_secs(4);
+/// Share of the measured throughput a variant may use
+const ABR_SAFETY: f64 = 0.8;
+
+/// One rendition of an adaptive source, e.g. a variant stream of an HLS master playlist
+#[derive(Serialize, Clone, Debug)]
+pub struct Variant {
+    pub id: usize,
+    /// Bits per second
+    pub bandwidth: u64,
+    pub width: u32,
+    pub height: u32,
+    /// Codec names, comma separated
+    pub codecs: String,
+}
+
+/// Variants of the input, empty unless the demuxer exposes one program per variant (HLS)
+pub fn list_variants(input: &Input) -> Vec<Variant> {
+    unsafe {
+        let context = input.as_ptr();
+        if (*context).nb_programs < 2 {
+            return Vec::new();
+        }
+        let mut variants = Vec::new();
+        for id in 0..(*context).nb_programs as usize {
+            let program = *(*context).programs.add(id);
+            let mut variant = Variant {
+                id,
+                bandwidth: program_bitrate(program).unwrap_or(0),
+                width: 0,
+                height: 0,
+                codecs: String::new(),
+            };
+            let mut codecs = Vec::new();
+            for stream_index in program_streams(program) {
+                let codecpar = (**(*context).streams.add(stream_index)).codecpar;
+                if (*codecpar).codec_type == AVMediaType::AVMEDIA_TYPE_VIDEO {
+                    variant.width = (*codecpar).width as u32;
+                    variant.height = (*codecpar).height as u32;
+                }
+                let name = CStr::from_ptr(avcodec_get_name((*codecpar).codec_id));
+                codecs.push(name.to_string_lossy().into_owned());
+            }
+            variant.codecs = codecs.join(",");
+            variants.push(variant);
+        }
+        variants
+    }
+}
+
+/// Video and audio stream indexes of a variant
+pub fn variant_streams(input: &Input, id: usize) -> (Option<usize>, Option<usize>) {
+    let mut video = None;
+    let mut audio = None;
+    unsafe {
+        let context = input.as_ptr();
+        if id >= (*context).nb_programs as usize {
+            return (None, None);
+        }
+        let program = *(*context).programs.add(id);
+        for stream_index in program_streams(program) {
+            let codecpar = (**(*context).streams.add(stream_index)).codecpar;
+            match (*codecpar).codec_type {
+                AVMediaType::AVMEDIA_TYPE_VIDEO if video.is_none() => video = Some(stream_index),
+                AVMediaType::AVMEDIA_TYPE_AUDIO if audio.is_none() => audio = Some(stream_index),
+                _ => {}
+            }
+        }
+    }
+    (video, audio)
+}
+
+/// Discard the streams of all other variants, so that their segments are not downloaded
+pub fn enable_variants(input: &mut Input, ids: &[usize]) {
+    unsafe {
+        let context = input.as_mut_ptr();
+        let mut enabled = vec![false; (*context).nb_streams as usize];
+        for &id in ids {
+            if id < (*context).nb_programs as usize {
+                for stream_index in program_streams(*(*context).programs.add(id)) {
+                    enabled[stream_index] = true;
+                }
+            }
+        }
+        for (stream_index, enabled) in enabled.into_iter().enumerate() {
+            let stream = *(*context).streams.add(stream_index);
+            (*stream).discard = if enabled {
+                AVDiscard::AVDISCARD_DEFAULT
+            } else {
+                AVDiscard::AVDISCARD_ALL
+            };
+        }
+    }
+}
+
+unsafe fn program_streams(program: *mut AVProgram) -> Vec<usize> {
+    (0..(*program).nb_stream_indexes as usize)
+        .map(|i| *(*program).stream_index.add(i) as usize)
+        .collect()
+}
+
+unsafe fn program_bitrate(program: *mut AVProgram) -> Option<u64> {
+    let key = b"variant_bitrate\0".as_ptr() as *const c_char;
+    let entry = av_dict_get((*program).metadata, key, ptr::null(), 0);
+    if entry.is_null() {
+        return None;
+    }
+    CStr::from_ptr((*entry).value).to_str().ok()?.parse().ok()
+}
+
+/// Picks variants from the throughput measured while reading packets
+pub struct AbrController {
+    bytes: u64,
+    read_time: Duration,
+    /// Bits per second, smoothed
+    estimate: Option<f64>,
+    last_decision: Instant,
+}
+
+impl Default for AbrController {
+    fn default() -> Self {
+        Self {
+            bytes: 0,
+            read_time: Duration::ZERO,
+            estimate: None,
+            last_decision: Instant::now(),
+        }
+    }
+}
+
+impl AbrController {
+    pub fn record(&mut self, bytes: usize, elapsed: Duration) {
+        self.bytes += bytes as u64;
+        self.read_time += elapsed;
+    }
+
+    /// Variant to switch to, if the throughput no longer matches `current`
+    pub fn choose(&mut self, variants: &[Variant], current: usize) -> Option<usize> {
+        if self.last_decision.elapsed() < ABR_INTERVAL || self.bytes == 0 {
+            return None;
+        }
+        let sample = self.bytes as f64 * 8.0 / self.read_time.as_secs_f64().max(0.001);
+        let estimate = match self.estimate {
+            Some(estimate) => estimate * 0.7 + sample * 0.3,
+            None => sample,
+        };
+        self.estimate = Some(estimate);
+        self.bytes = 0;
+        self.read_time = Duration::ZERO;
+        self.last_decision = Instant::now();
+
+        let budget = estimate * ABR_SAFETY;
+        let target = variants
+            .iter()
+            .filter(|v| v.bandwidth as f64 <= budget)
+            .max_by_key(|v| v.bandwidth)
+            .or_else(|| variants.iter().min_by_key(|v| v.bandwidth))?;
+        (target.id != current).then_some(target.id)
+    }
+}
@@ -1,17 +1,156 @@
 //@ts-ignore
 export class VideoElement extends Element {
 
+    #pendingCaptures = [];
+    #pendingThumbnails = null;
+    #pendingExport = null;
+    #pendingAnimation = null;
+    #pendingRequests = new Map();
+    #nextRequestId = 1;
+
     constructor() {
         // @ts-ignore
         super("video");
+        this.bindEvent("framecaptured", (e) => {
+            const pending = this.#pendingCaptures.shift();
+            if (!pending) {
+                return;
+            }
+            const {path, error} = e.detail;
+            if (error) {
+                pending.reject(new Error(error));
+            } else {
+                pending.resolve(path);
+            }
+        });
+        this.bindEvent("thumbnailsready", (e) => {
+            const pending = this.#pendingThumbnails;
+            this.#pendingThumbnails = null;
+            if (!pending) {
+                return;
+            }
+            const {sheet, error} = e.detail;
+            if (error) {
+                pending.reject(new Error(error));
+            } else {
+                pending.resolve(sheet);
+            }
+        });
+        this.bindEvent("exportprogress", (e) => {
+            this.#pendingExport?.onProgress?.(e.detail);
+        });
+        this.bindEvent("exportdone", (e) => {
+            const pending = this.#pendingExport;
+            this.#pendingExport = null;
+            if (!pending) {
+                return;
+            }
+            const {path, error} = e.detail;
+            if (error) {
+                pending.reject(new Error(error));
+            } else {
+                pending.resolve(path);
+            }
+        });
+        this.bindEvent("animationprogress", (e) => {
+            this.#pendingAnimation?.onProgress?.(e.detail);
+        });
+        this.bindEvent("animationdone", (e) => {
+            const pending = this.#pendingAnimation;
+            this.#pendingAnimation = null;
+            if (!pending) {
+                return;
+            }
+            const {path, error} = e.detail;
+            if (error) {
+                pending.reject(new Error(error));
+            } else {
+                pending.resolve(path);
+            }
+        });
+        this.bindEvent("ack", (e) => {
+            const {id, error} = e.detail;
+            const pending = this.#pendingRequests.get(id);
+            if (!pending) {
+                return;
+            }
+            this.#pendingRequests.delete(id);
+            if (error) {
+                pending.reject(new Error(error));
+            } else {
+                pending.resolve();
+            }
+        });
+    }
+
+    /**
+     * Send a request to the player thread, resolved when it is acknowledged
+     * @param send {(id: number) => void}
+     * @returns {Promise<void>}
+     */
+    #request(send) {
+        const id = this.#nextRequestId++;
+        return new Promise((resolve, reject) => {
+            this.#pendingRequests.set(id, {resolve, reject});
+            send(id);
+        });
     }
 
     setSrc(src) {
+        this.#cancelThumbnails();
         VideoBackend_set_src(this.handle, src);
     }
 
+    /**
+     * Play media held in memory
+     * @param buffer {ArrayBuffer}
+     */
+    setSrcBuffer(buffer) {
+        this.#cancelThumbnails();
+        VideoBackend_set_src_buffer(this.handle, buffer);
+    }
+
+    /**
+     * Start a source fed with appendBuffer, like a MediaSource
+     */
+    openStream() {
+        this.#cancelThumbnails();
+        VideoBackend_open_stream(this.handle);
+    }
+
+    /**
+     * Append bytes to the source started with openStream
+     * @param buffer {ArrayBuffer}
+     */
+    appendBuffer(buffer) {
+        VideoBackend_append_buffer(this.handle, buffer);
+    }
+
+    /**
+     * Signal that no more bytes will be appended
+     */
+    endOfStream() {
+        VideoBackend_end_of_stream(this.handle);
+    }
+
+    /**
+     * Load a source
+     * @param src {string}
+     * @returns {Promise<void>} resolved when metadata has been loaded
+     */
+    load(src) {
+        this.#cancelThumbnails();
+        return this.#request((id) => VideoBackend_load(this.handle, src, id));
+    }
+
+    /**
+     *
+     * @returns {Promise<void>} resolved when the first frame has been presented
+     * Resolved as well when a newer play supersedes it. Rejected when the source is
+     * stopped, replaced or fails before a frame is presented, or when no video is loaded
+     */
     play() {
-        VideoBackend_play(this.handle)
+        return this.#request((id) => VideoBackend_request_play(this.handle, id));
     }
 
     pause() {
@@ -22,17 +161,320 @@ export class VideoElement extends Element {
         VideoBackend_stop(this.handle);
     }
 
+    /**
+     * Stop playback and background jobs and release the audio device, e.g. before removing
+     * the element. Loading a new source starts over
+     */
+    destroy() {
+        this.#cancelThumbnails();
+        for (const pending of [this.#pendingExport, this.#pendingAnimation]) {
+            pending?.reject(new Error("cancelled"));
+        }
+        this.#pendingExport = null;
+        this.#pendingAnimation = null;
+        VideoBackend_destroy(this.handle);
+    }
+
+    /**
+     * @returns {"idle" | "loading" | "paused" | "playing" | "seeking" | "ended" | "error"}
+     */
+    get state() {
+        return VideoBackend_get_state(this.handle);
+    }
+
+    /**
+     * @returns {number} seconds
+     */
+    get currentTime() {
+        return VideoBackend_get_current_time(this.handle);
+    }
+
+    /**
+     * @returns {number} seconds
+     */
+    get duration() {
+        return VideoBackend_get_duration(this.handle);
+    }
+
+    /**
+     * @returns {[number, number][]} loaded time ranges in seconds
+     */
+    get buffered() {
+        return VideoBackend_get_buffered(this.handle);
+    }
+
+    /**
+     * @returns {string | null} message of the last error
+     */
+    get error() {
+        return VideoBackend_get_error(this.handle);
+    }
+
+    /**
+     * @returns {number} 0-1
+     */
+    get volume() {
+        return VideoBackend_get_volume(this.handle);
+    }
+
+    /**
+     * @param value {number} 0-1
+     */
+    set volume(value) {
+        VideoBackend_set_volume(this.handle, value);
+    }
+
+    /**
+     * @returns {number} playback speed, 1 for normal speed
+     */
+    get playbackRate() {
+        return VideoBackend_get_playback_rate(this.handle);
+    }
+
     /**
      *
      * @param time {number}
+     * @returns {Promise<void>} resolved when the frame at the target time has been presented
+     * Resolved as well when a newer seek supersedes it. Rejected when the source is not
+     * seekable, is stopped or replaced first, or when no video is loaded
      */
     seek(time) {
-        VideoBackend_seek(this.handle, time);
+        return this.#request((id) => VideoBackend_request_seek(this.handle, time, id));
+    }
+
+    /**
+     * Pause and show the next or previous frame
+     * @param delta {1 | -1}
+     * @returns {Promise<void>} resolved when the frame has been presented. Rejected when there
+     * is no such frame or no video is loaded
+     */
+    stepFrame(delta) {
+        return this.#request((id) => VideoBackend_request_step_frame(this.handle, delta, id));
+    }
+
+    /**
+     *
+     * @param fit {"contain" | "cover" | "fill" | "none" | "scale-down"}
+     */
+    setObjectFit(fit) {
+        VideoBackend_set_object_fit(this.handle, fit);
+    }
+
+    /**
+     *
+     * @param position {string} e.g. "center", "left top", "25% 75%"
+     */
+    setObjectPosition(position) {
+        VideoBackend_set_object_position(this.handle, position);
+    }
+
+    /**
+     * Filter used to scale frames to the view. "lanczos" pre-scales with Lanczos, any scaling
+     * left at draw time uses a Catmull-Rom cubic as an approximation
+     * @param quality {"fast-bilinear" | "bilinear" | "bicubic" | "lanczos"}
+     */
+    setScaleQuality(quality) {
+        VideoBackend_set_scale_quality(this.handle, quality);
+    }
+
+    /**
+     * Image shown until playback starts
+     * @param path {string} empty to clear
+     */
+    setPoster(path) {
+        VideoBackend_set_poster(this.handle, path);
+    }
+
+    /**
+     * Time of the frame previewed after loading, takes effect on the next setSrc
+     * @param time {number} negative to preview the first frame
+     */
+    setPreviewTime(time) {
+        VideoBackend_set_preview_time(this.handle, time);
+    }
+
+    /**
+     * Save the current frame at source resolution
+     * @param path {string}
+     * @param format {"png" | "jpeg" | "webp"}
+     * @param quality {number} 0-100, ignored by png
+     * @param burnSubtitles {boolean} draw the subtitle shown at the frame's time onto it
+     * @returns {Promise<string>} the written path
+     */
+    captureFrame(path, format = "png", quality = 90, burnSubtitles = false) {
+        return new Promise((resolve, reject) => {
+            this.#pendingCaptures.push({resolve, reject});
+            VideoBackend_capture_frame(this.handle, path, format, quality, burnSubtitles);
+        });
+    }
+
+    /**
+     * Write a sprite sheet of evenly spaced keyframes for seek bar previews,
+     * cancelled when the source changes
+     * @param options {{output: string, count?: number, tileWidth?: number, columns?: number}}
+     * @returns {Promise<{path: string, tile_width: number, tile_height: number, columns: number, thumbnails: {time: number, x: number, y: number}[]}>}
+     */
+    generateThumbnails(options) {
+        const {output, count = 100, tileWidth = 160, columns = 10} = options;
+        this.#cancelThumbnails();
+        return new Promise((resolve, reject) => {
+            this.#pendingThumbnails = {resolve, reject};
+            VideoBackend_generate_thumbnails(this.handle, output, count, tileWidth, columns);
+        });
+    }
+
+    #cancelThumbnails() {
+        const pending = this.#pendingThumbnails;
+        this.#pendingThumbnails = null;
+        pending?.reject(new Error("cancelled"));
+    }
+
+    /**
+     * Save a range of the current source to an mp4 or mkv file, replacing a running export.
+     * Mode "copy" remuxes from the keyframe before start, "encode" re-encodes with the active
+     * video filter
+     * @param options {{output: string, start: number, end: number, mode?: "copy" | "encode", onProgress?: (progress: number) => void}}
+     * @returns {Promise<string>} the written path
+     */
+    exportClip(options) {
+        const {output, start, end, mode = "copy", onProgress} = options;
+        this.cancelExport();
+        return new Promise((resolve, reject) => {
+            this.#pendingExport = {resolve, reject, onProgress};
+            VideoBackend_export_clip(this.handle, output, start, end, mode);
+        });
+    }
+
+    /**
+     * Stop the running export, its promise is rejected
+     */
+    cancelExport() {
+        const pending = this.#pendingExport;
+        this.#pendingExport = null;
+        VideoBackend_cancel_export(this.handle);
+        pending?.reject(new Error("cancelled"));
+    }
+
+    /**
+     * Save a range of the current source as an animated gif or webp, replacing a running
+     * animation export. Gif frames are quantised to a palette generated from the whole range
+     * @param options {{output: string, start: number, end: number, fps?: number, width?: number, format?: "gif" | "webp", onProgress?: (progress: number) => void}}
+     * @returns {Promise<string>} the written path
+     */
+    exportAnimation(options) {
+        const {output, start, end, fps = 10, width = 480, format = "gif", onProgress} = options;
+        this.cancelAnimation();
+        return new Promise((resolve, reject) => {
+            this.#pendingAnimation = {resolve, reject, onProgress};
+            VideoBackend_export_animation(this.handle, output, start, end, fps, width, format);
+        });
+    }
+
+    /**
+     * Stop the running animation export, its promise is rejected
+     */
+    cancelAnimation() {
+        const pending = this.#pendingAnimation;
+        this.#pendingAnimation = null;
+        VideoBackend_cancel_animation(this.handle);
+        pending?.reject(new Error("cancelled"));
+    }
+
+    /**
+     * Timeouts for opening a source and for a single network read, 0 to disable.
+     * Takes effect on the next source
+     * @param openTimeout {number} seconds
+     * @param readTimeout {number} seconds
+     */
+    setNetworkTimeouts(openTimeout, readTimeout) {
+        VideoBackend_set_network_timeouts(this.handle, openTimeout, readTimeout);
+    }
+
+    /**
+     * Live streams are played with low latency, without duration or seeking,
+     * and reconnected when lost. Takes effect on the next source
+     * @param mode {"auto" | "on" | "off"}
+     */
+    setLiveMode(mode) {
+        VideoBackend_set_live_mode(this.handle, mode);
+    }
+
+    /**
+     * Play a variant listed in loadedmetadata, -1 to select automatically from the throughput
+     * @param id {number}
+     */
+    setVariant(id) {
+        VideoBackend_set_variant(this.handle, id);
+    }
+
+    /**
+     * Filter the video with a libavfilter graph, e.g. "yadif,eq=contrast=1.2", empty to remove it.
+     * An invalid description fires error
+     * @param spec {string}
+     */
+    setVideoFilter(spec) {
+        VideoBackend_set_video_filter(this.handle, spec);
+    }
+
+    /**
+     * Fire audiolevels every intervalMs (0 to stop) with per-channel peak and RMS levels and,
+     * if bands is not 0, a spectrum of that many bands
+     * @param intervalMs {number}
+     * @param bands {number}
+     */
+    setAudioLevels(intervalMs, bands) {
+        VideoBackend_set_audio_levels(this.handle, intervalMs, bands);
+    }
+
+    /**
+     * Shift the audio against the video, positive plays it later and negative earlier
+     * @param ms {number}
+     */
+    setAudioDelay(ms) {
+        VideoBackend_set_audio_delay(this.handle, ms);
+    }
+
+    /**
+     * Filter the audio with a libavfilter graph, e.g. "dynaudnorm" for night mode or
+     * "equalizer=f=100:t=q:w=1:g=4", empty to remove it. An invalid description fires error
+     * @param spec {string}
+     */
+    setAudioFilter(spec) {
+        VideoBackend_set_audio_filter(this.handle, spec);
+    }
+
+    /**
+     * Deinterlace to field rate: "auto" once interlaced frames are detected, "on" or "off"
+     * @param mode {string}
+     */
+    setDeinterlace(mode) {
+        VideoBackend_set_deinterlace(this.handle, mode);
+    }
+
+    /**
+     * Restart from the beginning when the end is reached
+     * @param value {boolean}
+     */
+    setLoop(value) {
+        VideoBackend_set_loop(this.handle, value);
+    }
+
+    /**
+     * Repeat the range between start and end (A-B repeat)
+     * @param start {number} seconds
+     * @param end {number} seconds
+     */
+    setLoopRange(start, end) {
+        VideoBackend_set_loop_range(this.handle, start, end);
+    }
+
+    clearLoopRange() {
+        VideoBackend_clear_loop_range(this.handle);
     }
 
     /**
      *
-     * @param callback {(e: IEvent<{duration: number, width: number, height: number}>) => void}
+     * @param callback {(e: IEvent<{duration: number, width: number, height: number, seekable: boolean, live: boolean, variants: {id: number, bandwidth: number, width: number, height: number, codecs: string}[]}>) => void}
      */
     bindLoadedMetaData(callback) {
         this.bindEvent("loadedmetadata", callback);
@@ -66,6 +508,93 @@ export class VideoElement extends Element {
         this.bindEvent("stop", callback);
     }
 
+    /**
+     *
+     * @param callback {(e: IEvent<string>) => void}
+     */
+    bindError(callback) {
+        this.bindEvent("error", callback);
+    }
+
+    /**
+     * Fired when playback reaches the end of the media, after all audio has been played
+     * @param callback {() => void}
+     */
+    bindEnded(callback) {
+        this.bindEvent("ended", callback);
+    }
+
+    /**
+     * Fired when playback stalls waiting for network data or appended bytes
+     * @param callback {() => void}
+     */
+    bindWaiting(callback) {
+        this.bindEvent("waiting", callback);
+    }
+
+    /**
+     * Fired when data has been appended after waiting
+     * @param callback {() => void}
+     */
+    bindCanPlay(callback) {
+        this.bindEvent("canplay", callback);
+    }
+
+    /**
+     * Fired when playback resumes after waiting for network data
+     * @param callback {() => void}
+     */
+    bindPlaying(callback) {
+        this.bindEvent("playing", callback);
+    }
+
+    /**
+     * Fired when the downloaded range of a network source grows
+     * @param callback {(e: IEvent<[number, number][]>) => void}
+     */
+    bindBuffered(callback) {
+        this.bindEvent("buffered", callback);
+    }
+
+    /**
+     *
+     * @param callback {(e: IEvent<{pts: number, time: number, frame: number}>) => void}
+     */
+    bindFrameStep(callback) {
+        this.bindEvent("framestep", callback);
+    }
+
+    /**
+     * Levels of the audio being played, amplitudes in 0-1, see setAudioLevels
+     * @param callback {(e: IEvent<{peak: number[], rms: number[], spectrum: number[]}>) => void}
+     */
+    bindAudioLevels(callback) {
+        this.bindEvent("audiolevels", callback);
+    }
+
+    /**
+     *
+     * @param callback {(e: IEvent<number>) => void} progress in 0-1
+     */
+    bindThumbnailProgress(callback) {
+        this.bindEvent("thumbnailprogress", callback);
+    }
+
+}
+
+/**
+ * Read the container and stream metadata of a file or URL without starting playback.
+ * The source is opened in the background
+ * @param path {string}
+ * @returns {Promise<ProbeInfo>} rejected when the source cannot be opened
+ */
+async function probe(path) {
+    const {info, error} = await video_probe(path);
+    if (error) {
+        throw new Error(error);
+    }
+    return info;
 }
 
-globalThis.VideoElement = VideoElement;
\ No newline at end of file
+globalThis.VideoElement = VideoElement;
+globalThis.probe = probe;
\ No newline at end of file
@@ -1,21 +1,63 @@
-use crate::player::Meta;
+use crate::animation::{AnimationFormat, AnimationParams};
+use crate::audio_filter::AudioFilter;
+use crate::byte_stream::ByteStream;
+use crate::export::{ClipExporter, ExportMode, ExportParams};
+use crate::interrupt::Interrupter;
+use crate::levels::{AudioLevels, AudioMeter, MeterSettings};
+use crate::live::LiveMode;
+use crate::object_fit::{compute_dest_rect, ObjectFit, ObjectPosition};
+use crate::player::{FilterOptions, FrameStep, LoadRequest, Meta, PlayCallbacks, Reply};
 use crate::player_thread::{PlayParams, PlayerThread};
+use crate::scaling::{ScaleQuality, ScaleSettings};
+use crate::state::{PlaybackState, PlaybackStatus, SharedVolume};
+use crate::snapshot::{load_bitmap_from_rgba_bytes, parse_image_format, CaptureRequest};
+use crate::source::{MediaSource, OpenOptions};
+use crate::thumbnails::{ThumbnailExtractor, ThumbnailParams, ThumbnailSheet};
+use crate::video_filter::{filter_chain, DeinterlaceMode, FilterFailed, FilterKind, VideoFilter};
 use deft::element::{Element, ElementBackend, ElementWeak};
 use deft::event_loop::create_event_loop_fn_mut;
 use deft::render::RenderFn;
 use deft::{element_backend, event, js_methods, ok_or_return};
 use ffmpeg_next::frame::Video;
-use skia_safe::Image;
-use skia_safe::{AlphaType, Bitmap, ColorSpace, ColorType, ImageInfo, Paint, Rect};
-use std::ffi::{c_void};
+use skia_safe::{Data, Image};
+use skia_safe::{Canvas, ColorType, Paint, Rect, SamplingOptions};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use libc::{memcpy, size_t};
+use std::time::Duration;
 
 #[element_backend]
 pub struct VideoBackend {
     element: ElementWeak,
     frame: Arc<Mutex<Option<Video>>>,
     player: Option<PlayerThread>,
+    object_fit: ObjectFit,
+    object_position: ObjectPosition,
+    scale_settings: Arc<Mutex<ScaleSettings>>,
+    /// Resolution of the decoded (and filtered) video, frames may be pre-scaled below it
+    source_size: Arc<Mutex<Option<(f32, f32)>>>,
+    poster: Option<Image>,
+    /// Cleared once a frame other than the preview is presented
+    show_poster: Arc<AtomicBool>,
+    preview_time: Option<f32>,
+    /// Current source if it can be reopened, e.g. for thumbnails
+    src: Option<MediaSource>,
+    /// Source fed with `append_buffer`, closed when replaced so a blocked reader wakes up
+    stream: Option<ByteStream>,
+    open_options: OpenOptions,
+    /// Video and audio filters, kept across sources. Descriptions are stored once the player
+    /// has accepted them
+    filters: Arc<Mutex<FilterOptions>>,
+    thumbnails: Option<ThumbnailExtractor>,
+    export: Option<ClipExporter>,
+    animation: Option<ClipExporter>,
+    /// Milliseconds the audio is played after the video, negative to play it before
+    audio_delay: i32,
+    meter_settings: MeterSettings,
+    looping: bool,
+    loop_range: Option<(f32, f32)>,
+    status: Arc<Mutex<PlaybackStatus>>,
+    volume: SharedVolume,
 }
 
 #[event]
@@ -30,17 +72,676 @@ struct PauseEvent;
 #[event]
 struct StopEvent;
 
+#[event]
+struct EndedEvent;
+
+#[event]
+struct WaitingEvent;
+
+#[event]
+struct CanPlayEvent;
+
+#[event]
+struct PlayingEvent;
+
+#[event]
+struct BufferedEvent(Vec<(f32, f32)>);
+
+#[event]
+struct FrameStepEvent(FrameStep);
+
+#[event]
+struct AudioLevelsEvent(AudioLevels);
+
+#[event]
+struct ErrorEvent(String);
+
+#[derive(Serialize)]
+struct Ack {
+    id: u32,
+    error: Option<String>,
+}
+
+#[event]
+struct AckEvent(Ack);
+
 #[event]
 struct LoadedMetaData(Meta);
 
+#[derive(Serialize)]
+struct CaptureResult {
+    path: String,
+    error: Option<String>,
+}
+
+#[event]
+struct FrameCapturedEvent(CaptureResult);
+
+#[event]
+struct ThumbnailProgressEvent(f32);
+
+#[derive(Serialize)]
+struct ThumbnailResult {
+    sheet: Option<ThumbnailSheet>,
+    error: Option<String>,
+}
+
+#[event]
+struct ThumbnailsReadyEvent(ThumbnailResult);
+
+#[event]
+struct ExportProgressEvent(f32);
+
+#[derive(Serialize)]
+struct ExportResult {
+    path: String,
+    error: Option<String>,
+}
+
+#[event]
+struct ExportDoneEvent(ExportResult);
+
+#[event]
+struct AnimationProgressEvent(f32);
+
+#[event]
+struct AnimationDoneEvent(ExportResult);
+
 #[js_methods]
 impl VideoBackend {
     #[js_func]
     pub fn set_src(&mut self, src: String) {
-        println!("Setting src: {}", src);
-        let mut el = ok_or_return!(self.element.upgrade());
+        self.load_src(MediaSource::Url(src), None);
+    }
+
+    /// Play media held in memory
+    #[js_func]
+    pub fn set_src_buffer(&mut self, data: Vec<u8>) {
+        self.load_src(MediaSource::Buffer(data.into()), None);
+    }
+
+    /// Start a source fed with `append_buffer`, like a MediaSource. Fires `waiting` when the
+    /// player runs out of data and `canplay` once more has been appended
+    #[js_func]
+    pub fn open_stream(&mut self) {
+        let el = ok_or_return!(self.element.upgrade());
+        let waiting_emitter = el.create_event_emitter();
+        let can_play_emitter = el.create_event_emitter();
+        if self.player.is_none() {
+            self.player = self.create_player();
+        }
+        let Some(player) = &self.player else {
+            return;
+        };
+        let stream = ByteStream::default();
+        let reader = stream.reader(
+            player.interrupter(),
+            Box::new(move || waiting_emitter.emit(WaitingEvent)),
+            Box::new(move || can_play_emitter.emit(CanPlayEvent)),
+        );
+        self.load_src(MediaSource::Reader(Box::new(reader)), None);
+        self.stream = Some(stream);
+    }
+
+    #[js_func]
+    pub fn append_buffer(&mut self, data: Vec<u8>) {
+        if let Some(ref stream) = self.stream {
+            stream.append(&data);
+        }
+    }
+
+    /// No more data will be appended, playback ends once the appended data has been played
+    #[js_func]
+    pub fn end_of_stream(&mut self) {
+        if let Some(ref stream) = self.stream {
+            stream.end();
+        }
+    }
+
+    /// Like `set_src`, acknowledges `request_id` once metadata has been loaded
+    #[js_func]
+    pub fn load(&mut self, src: String, request_id: u32) {
+        let reply = self.create_reply(request_id);
+        self.load_src(MediaSource::Url(src), reply);
+    }
+
+    #[js_func]
+    pub fn play(&mut self) {
+        self.play_with_reply(None);
+    }
+
+    /// Like `play`, acknowledges `request_id` once the first frame has been presented
+    #[js_func]
+    pub fn request_play(&mut self, request_id: u32) {
+        let reply = self.create_reply(request_id);
+        self.play_with_reply(reply);
+    }
+
+    #[js_func]
+    pub fn seek(&mut self, value: f32) {
+        self.seek_with_reply(value, None);
+    }
+
+    /// Like `seek`, acknowledges `request_id` once the target frame has been presented
+    #[js_func]
+    pub fn request_seek(&mut self, value: f32, request_id: u32) {
+        let reply = self.create_reply(request_id);
+        self.seek_with_reply(value, reply);
+    }
+
+    #[js_func]
+    pub fn pause(&mut self) {
+        if let Some(ref mut player) = self.player {
+            player.pause();
+        }
+    }
+
+    /// Pause and show the next (+1) or previous (-1) frame, fires `framestep` with its pts or
+    /// `error` if there is no such frame
+    #[js_func]
+    pub fn step_frame(&mut self, delta: i32) {
+        let el = ok_or_return!(self.element.upgrade());
+        let emitter = el.create_event_emitter();
+        let reply: Reply = Box::new(move |result| {
+            if let Err(e) = result {
+                emitter.emit(ErrorEvent(format!("step frame failed: {}", e)));
+            }
+        });
+        self.step_frame_with_reply(delta, reply);
+    }
+
+    /// Like `step_frame`, acknowledges `request_id` once the frame has been presented
+    #[js_func]
+    pub fn request_step_frame(&mut self, delta: i32, request_id: u32) {
+        if let Some(reply) = self.create_reply(request_id) {
+            self.step_frame_with_reply(delta, reply);
+        }
+    }
+
+    #[js_func]
+    pub fn stop(&mut self) {
+        if let Some(ref mut player) = self.player {
+            player.stop();
+        }
+    }
+
+    /// Stop the player thread and background jobs, release the audio device and the frame
+    /// buffer. Returns once the threads have exited, without waiting for garbage collection
+    #[js_func]
+    pub fn destroy(&mut self) {
+        self.release();
+    }
+
+    #[js_func]
+    pub fn set_object_fit(&mut self, value: String) {
+        if let Some(fit) = ObjectFit::parse(&value) {
+            self.object_fit = fit;
+            self.mark_dirty();
+        }
+    }
+
+    #[js_func]
+    pub fn set_object_position(&mut self, value: String) {
+        if let Some(position) = ObjectPosition::parse(&value) {
+            self.object_position = position;
+            self.mark_dirty();
+        }
+    }
+
+    #[js_func]
+    pub fn set_scale_quality(&mut self, value: String) {
+        if let Some(quality) = ScaleQuality::parse(&value) {
+            self.scale_settings.lock().unwrap().quality = quality;
+            self.mark_dirty();
+        }
+    }
+
+    /// Image shown instead of the preview frame until playback starts, empty path to clear
+    #[js_func]
+    pub fn set_poster(&mut self, path: String) {
+        self.poster = if path.is_empty() {
+            None
+        } else {
+            match std::fs::read(&path) {
+                Ok(bytes) => Image::from_encoded(Data::new_copy(&bytes)),
+                Err(e) => {
+                    println!("failed to load poster {}: {:?}", path, e);
+                    None
+                }
+            }
+        };
+        self.mark_dirty();
+    }
+
+    /// Time of the frame previewed after loading, negative to preview the first frame
+    #[js_func]
+    pub fn set_preview_time(&mut self, time: f32) {
+        self.preview_time = if time >= 0.0 { Some(time) } else { None };
+    }
+
+    /// Save the current frame at source resolution, with the subtitle shown at its time drawn
+    /// onto it if `burn_subtitles` is set. Fires `framecaptured` when done
+    #[js_func]
+    pub fn capture_frame(
+        &mut self,
+        path: String,
+        format: String,
+        quality: u32,
+        burn_subtitles: bool,
+    ) {
+        let el = ok_or_return!(self.element.upgrade());
+        let emitter = el.create_event_emitter();
+        let requested_path = path.clone();
+        let on_done = Box::new(move |result: Result<String, anyhow::Error>| {
+            let result = CaptureResult {
+                path: requested_path,
+                error: result.err().map(|e| e.to_string()),
+            };
+            emitter.emit(FrameCapturedEvent(result));
+        });
+        let (Some(player), Some(format)) = (&self.player, parse_image_format(&format)) else {
+            on_done(Err(anyhow::anyhow!("no video loaded or unsupported format")));
+            return;
+        };
+        let subtitles = match self.src.as_ref().filter(|_| burn_subtitles) {
+            Some(src) => match src.try_clone() {
+                Some(src) => Some(src),
+                None => {
+                    on_done(Err(anyhow::anyhow!("source cannot be reopened for subtitles")));
+                    return;
+                }
+            },
+            None => None,
+        };
+        player.capture_frame(CaptureRequest {
+            path,
+            format,
+            quality,
+            subtitles,
+            on_done,
+        });
+    }
+
+    /// Write a sprite sheet of `count` evenly spaced keyframes of the current source to `output`,
+    /// fires `thumbnailprogress` while extracting and `thumbnailsready` when done
+    #[js_func]
+    pub fn generate_thumbnails(
+        &mut self,
+        output: String,
+        count: usize,
+        tile_width: u32,
+        columns: usize,
+    ) {
+        let el = ok_or_return!(self.element.upgrade());
+        let progress_emitter = el.create_event_emitter();
+        let ready_emitter = el.create_event_emitter();
+        let on_done = Box::new(move |result: Result<ThumbnailSheet, anyhow::Error>| {
+            let result = match result {
+                Ok(sheet) => ThumbnailResult {
+                    sheet: Some(sheet),
+                    error: None,
+                },
+                Err(e) => ThumbnailResult {
+                    sheet: None,
+                    error: Some(e.to_string()),
+                },
+            };
+            ready_emitter.emit(ThumbnailsReadyEvent(result));
+        });
+        let Some(src) = self.src.as_ref().and_then(|src| src.try_clone()) else {
+            on_done(Err(anyhow::anyhow!("no video loaded or source cannot be reopened")));
+            return;
+        };
+        self.thumbnails = Some(ThumbnailExtractor::start(ThumbnailParams {
+            src,
+            output,
+            count,
+            tile_width,
+            columns,
+            on_progress: Box::new(move |progress| {
+                progress_emitter.emit(ThumbnailProgressEvent(progress));
+            }),
+            on_done,
+        }));
+    }
+
+    /// Save the range between start and end (in seconds) of the current source to `output`, an
+    /// mp4 or mkv file. Mode `copy` remuxes from the keyframe before start, `encode` re-encodes
+    /// with the active video filter. Fires `exportprogress` and `exportdone`, cancels a running
+    /// export
+    #[js_func]
+    pub fn export_clip(&mut self, output: String, start: f32, end: f32, mode: String) {
+        let el = ok_or_return!(self.element.upgrade());
+        let progress_emitter = el.create_event_emitter();
+        let done_emitter = el.create_event_emitter();
+        let path = output.clone();
+        let on_done = Box::new(move |result: Result<String, anyhow::Error>| {
+            let result = ExportResult {
+                path,
+                error: result.err().map(|e| e.to_string()),
+            };
+            done_emitter.emit(ExportDoneEvent(result));
+        });
+        let Some(mode) = ExportMode::parse(&mode) else {
+            on_done(Err(anyhow::anyhow!("unsupported export mode: {}", mode)));
+            return;
+        };
+        if end <= start {
+            on_done(Err(anyhow::anyhow!("invalid range")));
+            return;
+        }
+        let Some(src) = self.src.as_ref().and_then(|src| src.try_clone()) else {
+            on_done(Err(anyhow::anyhow!("no video loaded or source cannot be reopened")));
+            return;
+        };
+        // Automatic deinterlacing depends on the decoded frames, only a forced one is applied
+        let video_filter = {
+            let filters = self.filters.lock().unwrap();
+            filter_chain(filters.deinterlace, false, filters.video.as_deref())
+        };
+        self.export = Some(ClipExporter::start(ExportParams {
+            src,
+            output,
+            start,
+            end,
+            mode,
+            video_filter,
+            on_progress: Box::new(move |progress| {
+                progress_emitter.emit(ExportProgressEvent(progress));
+            }),
+            on_done,
+        }));
+    }
 
+    /// Stop the running export and delete its output, `exportdone` is not fired
+    #[js_func]
+    pub fn cancel_export(&mut self) {
+        if let Some(export) = self.export.take() {
+            export.cancel();
+        }
+    }
+
+    /// Save the range between start and end (in seconds) of the current source to `output` as
+    /// an animated `gif` or `webp`, at `fps` frames per second and `width` pixels wide (0 keeps
+    /// the source width). Fires `animationprogress` and `animationdone`, cancels a running one
+    #[js_func]
+    pub fn export_animation(
+        &mut self,
+        output: String,
+        start: f32,
+        end: f32,
+        fps: f32,
+        width: u32,
+        format: String,
+    ) {
+        let el = ok_or_return!(self.element.upgrade());
+        let progress_emitter = el.create_event_emitter();
+        let done_emitter = el.create_event_emitter();
+        let path = output.clone();
+        let on_done = Box::new(move |result: Result<String, anyhow::Error>| {
+            let result = ExportResult {
+                path,
+                error: result.err().map(|e| e.to_string()),
+            };
+            done_emitter.emit(AnimationDoneEvent(result));
+        });
+        let Some(format) = AnimationFormat::parse(&format) else {
+            on_done(Err(anyhow::anyhow!("unsupported animation format: {}", format)));
+            return;
+        };
+        if end <= start {
+            on_done(Err(anyhow::anyhow!("invalid range")));
+            return;
+        }
+        if fps.is_nan() || fps <= 0.0 {
+            on_done(Err(anyhow::anyhow!("invalid frame rate: {}", fps)));
+            return;
+        }
+        let Some(src) = self.src.as_ref().and_then(|src| src.try_clone()) else {
+            on_done(Err(anyhow::anyhow!("no video loaded or source cannot be reopened")));
+            return;
+        };
+        let video_filter = {
+            let filters = self.filters.lock().unwrap();
+            filter_chain(filters.deinterlace, false, filters.video.as_deref())
+        };
+        self.animation = Some(ClipExporter::start_animation(AnimationParams {
+            src,
+            output,
+            start,
+            end,
+            fps,
+            width,
+            format,
+            video_filter,
+            on_progress: Box::new(move |progress| {
+                progress_emitter.emit(AnimationProgressEvent(progress));
+            }),
+            on_done,
+        }));
+    }
+
+    /// Stop the running animation export and delete its output, `animationdone` is not fired
+    #[js_func]
+    pub fn cancel_animation(&mut self) {
+        if let Some(animation) = self.animation.take() {
+            animation.cancel();
+        }
+    }
+
+    /// Timeouts in seconds for opening a source and for a single network read, 0 to disable.
+    /// Takes effect on the next source
+    #[js_func]
+    pub fn set_network_timeouts(&mut self, open_timeout: f32, read_timeout: f32) {
+        let to_duration = |secs: f32| (secs > 0.0).then(|| Duration::from_secs_f32(secs));
+        self.open_options.open_timeout = to_duration(open_timeout);
+        self.open_options.read_timeout = to_duration(read_timeout);
+    }
+
+    /// One of auto, on or off. Live streams are played with low latency, without duration or
+    /// seeking, and reconnected when lost. Takes effect on the next source
+    #[js_func]
+    pub fn set_live_mode(&mut self, value: String) {
+        if let Some(mode) = LiveMode::parse(&value) {
+            self.open_options.live = mode;
+        }
+    }
+
+    /// Play the variant with this id from `loadedmetadata`, negative to select automatically
+    /// from the measured throughput
+    #[js_func]
+    pub fn set_variant(&mut self, id: i32) {
+        if let Some(ref player) = self.player {
+            player.select_variant(usize::try_from(id).ok());
+        }
+    }
+
+    /// Filter the video with a libavfilter graph, e.g. `yadif,eq=contrast=1.2`, empty to
+    /// remove it. Applied immediately, fires `error` if the description is invalid
+    #[js_func]
+    pub fn set_video_filter(&mut self, spec: String) {
+        let el = ok_or_return!(self.element.upgrade());
+        let emitter = el.create_event_emitter();
+        let spec = Some(spec.trim().to_string()).filter(|spec| !spec.is_empty());
+        let filters = self.filters.clone();
+        let accepted = spec.clone();
+        let reply: Reply = Box::new(move |result| match result {
+            Ok(()) => filters.lock().unwrap().video = accepted,
+            Err(e) => emitter.emit(ErrorEvent(format!("invalid video filter: {}", e))),
+        });
+        match &self.player {
+            Some(player) => player.set_video_filter(spec, reply),
+            None => reply(spec.as_deref().map_or(Ok(()), VideoFilter::check)),
+        }
+    }
+
+    /// Fire `audiolevels` with the peak and RMS level of each channel every `interval_ms`, 0 to
+    /// stop, and a spectrum of `bands` bands, 0 to leave it out. Measured on the samples sent
+    /// to the output device
+    #[js_func]
+    pub fn set_audio_levels(&mut self, interval_ms: u32, bands: u32) {
+        self.meter_settings.set(interval_ms, bands);
+    }
+
+    /// Shift the audio against the video to correct A/V offsets, e.g. of Bluetooth headsets.
+    /// Positive plays the audio later, negative earlier. Applies immediately
+    #[js_func]
+    pub fn set_audio_delay(&mut self, ms: f32) {
+        self.audio_delay = ms.round() as i32;
+        if let Some(ref player) = self.player {
+            player.set_audio_delay(self.audio_delay);
+        }
+    }
+
+    /// Filter the audio with a libavfilter graph before resampling, e.g. `dynaudnorm`,
+    /// `equalizer=f=100:t=q:w=1:g=4` or `pan=stereo|c0=c1|c1=c0`, empty to remove it. Fires
+    /// `error` if the description is invalid
+    #[js_func]
+    pub fn set_audio_filter(&mut self, spec: String) {
+        let el = ok_or_return!(self.element.upgrade());
+        let emitter = el.create_event_emitter();
+        let spec = Some(spec.trim().to_string()).filter(|spec| !spec.is_empty());
+        let filters = self.filters.clone();
+        let accepted = spec.clone();
+        let reply: Reply = Box::new(move |result| match result {
+            Ok(()) => filters.lock().unwrap().audio = accepted,
+            Err(e) => emitter.emit(ErrorEvent(format!("invalid audio filter: {}", e))),
+        });
+        match &self.player {
+            Some(player) => player.set_audio_filter(spec, reply),
+            None => reply(spec.as_deref().map_or(Ok(()), AudioFilter::check)),
+        }
+    }
+
+    /// One of auto, on or off. Interlaced video is shown at field rate, auto deinterlaces once
+    /// a frame flagged as interlaced is decoded
+    #[js_func]
+    pub fn set_deinterlace(&mut self, value: String) {
+        if let Some(mode) = DeinterlaceMode::parse(&value) {
+            self.filters.lock().unwrap().deinterlace = mode;
+            if let Some(ref player) = self.player {
+                player.set_deinterlace(mode);
+            }
+        }
+    }
+
+    /// Restart from the beginning when the end is reached
+    #[js_func]
+    pub fn set_loop(&mut self, value: bool) {
+        self.looping = value;
+        if let Some(ref player) = self.player {
+            player.set_loop(value);
+        }
+    }
+
+    /// Repeat the range between start and end (in seconds)
+    #[js_func]
+    pub fn set_loop_range(&mut self, start: f32, end: f32) {
+        self.loop_range = if end > start {
+            Some((start.max(0.0), end))
+        } else {
+            None
+        };
+        if let Some(ref player) = self.player {
+            player.set_loop_range(self.loop_range);
+        }
+    }
+
+    #[js_func]
+    pub fn clear_loop_range(&mut self) {
+        self.loop_range = None;
+        if let Some(ref player) = self.player {
+            player.set_loop_range(None);
+        }
+    }
+
+    /// One of idle, loading, paused, playing, seeking, ended or error
+    #[js_func]
+    pub fn get_state(&mut self) -> String {
+        self.status.lock().unwrap().state.as_str().to_string()
+    }
+
+    #[js_func]
+    pub fn get_current_time(&mut self) -> f32 {
+        self.status.lock().unwrap().current_time
+    }
+
+    #[js_func]
+    pub fn get_duration(&mut self) -> f32 {
+        self.status.lock().unwrap().duration
+    }
+
+    #[js_func]
+    pub fn get_buffered(&mut self) -> Vec<(f32, f32)> {
+        self.status.lock().unwrap().buffered.clone()
+    }
+
+    #[js_func]
+    pub fn get_error(&mut self) -> Option<String> {
+        self.status.lock().unwrap().error.clone()
+    }
+
+    #[js_func]
+    pub fn get_volume(&mut self) -> f32 {
+        self.volume.get()
+    }
+
+    /// Output volume in 0-1
+    #[js_func]
+    pub fn set_volume(&mut self, volume: f32) {
+        let volume = volume.clamp(0.0, 1.0);
+        self.volume.set(volume);
+        self.status.lock().unwrap().volume = volume;
+    }
+
+    #[js_func]
+    pub fn get_playback_rate(&mut self) -> f32 {
+        self.status.lock().unwrap().rate
+    }
+}
+
+impl VideoBackend {
+    /// Play from any source, e.g. a `Read + Seek` implementation wrapped in `MediaSource::Reader`
+    pub fn set_source(&mut self, source: MediaSource) {
+        self.load_src(source, None);
+    }
+
+    fn load_src(&mut self, source: MediaSource, reply: Option<Reply>) {
+        println!("Setting src: {}", source.describe());
+        if let Some(stream) = self.stream.take() {
+            stream.close();
+        }
+        if self.player.is_none() {
+            self.player = self.create_player();
+        }
+        let Some(player) = &self.player else {
+            return;
+        };
+
+        self.frame.lock().unwrap().take();
+        self.show_poster.store(true, Ordering::Relaxed);
+        // Thumbnails of the previous source are no longer useful
+        self.thumbnails = None;
+        self.src = source.try_clone();
+        self.status.lock().unwrap().reset(PlaybackState::Loading);
+        player.load(LoadRequest {
+            source,
+            options: self.open_options,
+            preview_time: self.preview_time,
+            filters: self.filters.lock().unwrap().clone(),
+            reply,
+        });
+        player.set_loop(self.looping);
+        player.set_loop_range(self.loop_range);
+        player.set_audio_delay(self.audio_delay);
+        // self.play();
+    }
+
+    /// Start the player thread, it is reused for every source of this element
+    fn create_player(&self) -> Option<PlayerThread> {
+        let el = ok_or_return!(self.element.upgrade(), None);
         let frame = self.frame.clone();
+        let show_poster = self.show_poster.clone();
         let weak_element = self.element.clone();
         let mut dirty_marker = create_event_loop_fn_mut(move |_| {
             if let Ok(mut el) = weak_element.upgrade() {
@@ -51,55 +752,170 @@ impl VideoBackend {
         let meta_loaded_emitter = el.create_event_emitter();
         let progress_emitter = el.create_event_emitter();
         let stop_emitter = el.create_event_emitter();
+        let ended_emitter = el.create_event_emitter();
+        let error_emitter = el.create_event_emitter();
+        let buffered_emitter = el.create_event_emitter();
+        let waiting_emitter = el.create_event_emitter();
+        let playing_emitter = el.create_event_emitter();
+        let levels_emitter = el.create_event_emitter();
+        let play_emitter = el.create_event_emitter();
+        let pause_emitter = el.create_event_emitter();
+        let meta_status = self.status.clone();
+        let progress_status = self.status.clone();
+        let stop_status = self.status.clone();
+        let ended_status = self.status.clone();
+        let error_status = self.status.clone();
+        let error_filters = self.filters.clone();
+        let buffered_status = self.status.clone();
+        let play_status = self.status.clone();
+        let pause_status = self.status.clone();
+        let seeking_status = self.status.clone();
+        let play_show_poster = self.show_poster.clone();
         let play_params = PlayParams {
-            path: src,
+            scale_settings: self.scale_settings.clone(),
+            source_size: self.source_size.clone(),
+            volume: self.volume.clone(),
+            interrupter: Interrupter::new(Box::new(move |stalled| {
+                if stalled {
+                    waiting_emitter.emit(WaitingEvent);
+                } else {
+                    playing_emitter.emit(PlayingEvent);
+                }
+            })),
             on_meta_loaded: Box::new(move |meta| {
+                {
+                    let mut status = meta_status.lock().unwrap();
+                    status.duration = meta.duration;
+                    status.buffered = vec![(0.0, meta.duration)];
+                    if status.state == PlaybackState::Loading {
+                        status.state = PlaybackState::Paused;
+                    }
+                }
                 meta_loaded_emitter.emit(LoadedMetaData(meta));
             }),
-            on_progress: Box::new(move |progress| {
-                progress_emitter.emit(ProgressEvent(progress));
-            }),
-            on_stop: Box::new(move || {
-                stop_emitter.emit(StopEvent);
-            }),
-            renderer: Box::new(move |f| {
-                let mut frame = frame.lock().unwrap();
-                frame.replace(f);
-                dirty_marker.call(());
-            }),
+            callbacks: PlayCallbacks {
+                renderer: Box::new(move |f, preview| {
+                    // Seeking or stepping while paused shows the new frame over the poster
+                    if !preview {
+                        show_poster.store(false, Ordering::Relaxed);
+                    }
+                    let mut frame = frame.lock().unwrap();
+                    frame.replace(f);
+                    dirty_marker.call(());
+                }),
+                on_progress: Box::new(move |progress| {
+                    progress_status.lock().unwrap().update_time(progress);
+                    progress_emitter.emit(ProgressEvent(progress));
+                }),
+                on_buffered: Box::new(move |end| {
+                    let buffered = vec![(0.0, end)];
+                    buffered_status.lock().unwrap().buffered = buffered.clone();
+                    buffered_emitter.emit(BufferedEvent(buffered));
+                }),
+                on_stop: Box::new(move || {
+                    stop_status.lock().unwrap().state = PlaybackState::Idle;
+                    stop_emitter.emit(StopEvent);
+                }),
+                on_ended: Box::new(move || {
+                    ended_status.lock().unwrap().state = PlaybackState::Ended;
+                    ended_emitter.emit(EndedEvent);
+                }),
+                on_play: Box::new(move || {
+                    play_show_poster.store(false, Ordering::Relaxed);
+                    play_status.lock().unwrap().set_playing(true);
+                    play_emitter.emit(PlayEvent);
+                }),
+                on_pause: Box::new(move || {
+                    pause_status.lock().unwrap().set_playing(false);
+                    pause_emitter.emit(PauseEvent);
+                }),
+                on_seeking: Box::new(move || {
+                    seeking_status.lock().unwrap().start_seeking();
+                }),
+                on_error: Box::new(move |error| {
+                    let failed_filter = error.downcast_ref::<FilterFailed>().map(|e| e.kind);
+                    if let Some(kind) = failed_filter {
+                        // Not applied to later sources either
+                        let mut filters = error_filters.lock().unwrap();
+                        match kind {
+                            FilterKind::Video => filters.video = None,
+                            FilterKind::Audio => filters.audio = None,
+                        }
+                    }
+                    let error = error.to_string();
+                    {
+                        let mut status = error_status.lock().unwrap();
+                        if failed_filter.is_some() {
+                            // Playback goes on without the filter
+                            status.error = Some(error.clone());
+                        } else {
+                            status.set_error(error.clone());
+                        }
+                    }
+                    error_emitter.emit(ErrorEvent(error));
+                }),
+                audio_meter: AudioMeter {
+                    settings: self.meter_settings.clone(),
+                    on_levels: Arc::new(Mutex::new(Box::new(move |levels| {
+                        levels_emitter.emit(AudioLevelsEvent(levels));
+                    }))),
+                },
+            },
         };
-        self.player = Some(PlayerThread::start(play_params));
-        // self.play();
+        Some(PlayerThread::start(play_params))
     }
 
-    #[js_func]
-    pub fn play(&mut self) {
-        let el = self.element.clone();
+    fn play_with_reply(&mut self, reply: Option<Reply>) {
         if let Some(ref player) = self.player {
-            player.play();
-            el.emit(PlayEvent);
+            player.play(reply);
+        } else if let Some(reply) = reply {
+            reply(Err(anyhow::anyhow!("no video loaded")));
         }
     }
 
-    #[js_func]
-    pub fn seek(&mut self, value: f32) {
-        if let Some(ref mut player) = self.player {
-            player.seek(value);
-        }
+    fn step_frame_with_reply(&mut self, delta: i32, reply: Reply) {
+        let el = ok_or_return!(self.element.upgrade());
+        let emitter = el.create_event_emitter();
+        self.pause();
+        let Some(player) = &self.player else {
+            reply(Err(anyhow::anyhow!("no video loaded")));
+            return;
+        };
+        player.step_frame(
+            delta,
+            Box::new(move |result| match result {
+                Ok(step) => {
+                    emitter.emit(FrameStepEvent(step));
+                    reply(Ok(()));
+                }
+                Err(e) => reply(Err(e)),
+            }),
+        );
     }
 
-    #[js_func]
-    pub fn pause(&mut self) {
+    fn seek_with_reply(&mut self, value: f32, reply: Option<Reply>) {
         if let Some(ref mut player) = self.player {
-            player.pause();
-            self.element.emit(PauseEvent);
+            player.seek(value, reply);
+        } else if let Some(reply) = reply {
+            reply(Err(anyhow::anyhow!("no video loaded")));
         }
     }
 
-    #[js_func]
-    pub fn stop(&mut self) {
-        if let Some(ref mut player) = self.player {
-            player.stop();
+    /// Reply that acknowledges `request_id` to JS through the `ack` event
+    fn create_reply(&self, request_id: u32) -> Option<Reply> {
+        let el = ok_or_return!(self.element.upgrade(), None);
+        let emitter = el.create_event_emitter();
+        Some(Box::new(move |result: Result<(), anyhow::Error>| {
+            emitter.emit(AckEvent(Ack {
+                id: request_id,
+                error: result.err().map(|e| e.to_string()),
+            }));
+        }))
+    }
+
+    fn mark_dirty(&self) {
+        if let Ok(mut el) = self.element.upgrade() {
+            el.mark_dirty(false);
         }
     }
 }
@@ -113,16 +929,69 @@ impl ElementBackend for VideoBackend {
         element.register_js_event::<PlayEvent>("play");
         element.register_js_event::<PauseEvent>("pause");
         element.register_js_event::<StopEvent>("stop");
+        element.register_js_event::<EndedEvent>("ended");
+        element.register_js_event::<WaitingEvent>("waiting");
+        element.register_js_event::<CanPlayEvent>("canplay");
+        element.register_js_event::<PlayingEvent>("playing");
+        element.register_js_event::<BufferedEvent>("buffered");
+        element.register_js_event::<FrameStepEvent>("framestep");
+        element.register_js_event::<AudioLevelsEvent>("audiolevels");
+        element.register_js_event::<ErrorEvent>("error");
+        element.register_js_event::<AckEvent>("ack");
         element.register_js_event::<LoadedMetaData>("loadedmetadata");
+        element.register_js_event::<FrameCapturedEvent>("framecaptured");
+        element.register_js_event::<ThumbnailProgressEvent>("thumbnailprogress");
+        element.register_js_event::<ThumbnailsReadyEvent>("thumbnailsready");
+        element.register_js_event::<ExportProgressEvent>("exportprogress");
+        element.register_js_event::<ExportDoneEvent>("exportdone");
+        element.register_js_event::<AnimationProgressEvent>("animationprogress");
+        element.register_js_event::<AnimationDoneEvent>("animationdone");
         VideoBackendData {
             element: element.as_weak(),
             frame: Arc::new(Mutex::new(None)),
             player: None,
+            object_fit: ObjectFit::Contain,
+            object_position: ObjectPosition::default(),
+            scale_settings: Arc::new(Mutex::new(ScaleSettings::default())),
+            source_size: Arc::new(Mutex::new(None)),
+            poster: None,
+            show_poster: Arc::new(AtomicBool::new(true)),
+            preview_time: None,
+            src: None,
+            stream: None,
+            open_options: OpenOptions::default(),
+            filters: Arc::new(Mutex::new(FilterOptions::default())),
+            thumbnails: None,
+            export: None,
+            animation: None,
+            audio_delay: 0,
+            meter_settings: MeterSettings::default(),
+            looping: false,
+            loop_range: None,
+            status: Arc::new(Mutex::new(PlaybackStatus::default())),
+            volume: SharedVolume::default(),
         }
         .to_ref()
     }
 
     fn render(&mut self) -> RenderFn {
+        let element = ok_or_return!(self.element.upgrade_mut(), RenderFn::empty());
+        let view_size = element.get_size();
+        let clip_rect = Rect::from_wh(view_size.0, view_size.1);
+        if self.show_poster.load(Ordering::Relaxed) {
+            if let Some(poster) = self.poster.clone() {
+                let rect = compute_dest_rect(
+                    view_size,
+                    (poster.width() as f32, poster.height() as f32),
+                    self.object_fit,
+                    self.object_position,
+                );
+                let sampling = self.scale_settings.lock().unwrap().quality.sampling_options();
+                return RenderFn::new(move |painter| {
+                    draw_clipped_image(&painter.canvas, &poster, &clip_rect, &rect, sampling);
+                });
+            }
+        }
         let img = {
             match self.frame.lock().unwrap().as_ref() {
                 None => return RenderFn::empty(),
@@ -138,32 +1007,71 @@ impl ElementBackend for VideoBackend {
                 }
             }
         };
-        let element = ok_or_return!(self.element.upgrade_mut(), RenderFn::empty());
-        let view_size = element.get_size();
-        let (view_width, view_height) = (view_size.0 as i32, view_size.1 as i32);
-        let (image_width, image_height) = (img.width(), img.height());
-        let mut rect_width = view_width;
-        let mut rect_height = image_height * rect_width / image_width;
-        if rect_height > view_height {
-            rect_height = view_height;
-            rect_width = image_width * rect_height / image_height;
-        }
-        let left = (view_width - rect_width) / 2;
-        let top = (view_height - rect_height) / 2;
-        let rect = Rect::new(
-            left as f32,
-            top as f32,
-            (left + rect_width) as f32,
-            (top + rect_height) as f32,
+        let image_size = self
+            .source_size
+            .lock()
+            .unwrap()
+            .unwrap_or((img.width() as f32, img.height() as f32));
+        let rect = compute_dest_rect(
+            view_size,
+            image_size,
+            self.object_fit,
+            self.object_position,
         );
+        let scale_settings = self.scale_settings.clone();
+        let sampling = scale_settings.lock().unwrap().quality.sampling_options();
         RenderFn::new(move |painter| {
-            painter
-                .canvas
-                .draw_image_rect(&img, None, &rect, &Paint::default());
+            let canvas = &painter.canvas;
+            // Let the decoder thread scale frames to the size actually drawn on screen
+            let (device_rect, _) = canvas.local_to_device_as_3x3().map_rect(rect);
+            let target_size = (
+                device_rect.width().round() as u32,
+                device_rect.height().round() as u32,
+            );
+            scale_settings.lock().unwrap().target_size = Some(target_size);
+            draw_clipped_image(canvas, &img, &clip_rect, &rect, sampling);
         })
     }
 }
 
+impl VideoBackendData {
+    /// Stop all background work and free the frame buffer, the element can be loaded again
+    fn release(&mut self) {
+        // Each joins its thread once cancelled
+        self.thumbnails = None;
+        self.export = None;
+        self.animation = None;
+        if let Some(stream) = self.stream.take() {
+            stream.close();
+        }
+        // Joins the player thread, which closes the audio device on exit
+        self.player = None;
+        self.frame.lock().unwrap().take();
+        self.src = None;
+        self.status.lock().unwrap().reset(PlaybackState::Idle);
+    }
+}
+
+impl Drop for VideoBackendData {
+    fn drop(&mut self) {
+        // Also covers elements that are dropped without `destroy`
+        self.release();
+    }
+}
+
+fn draw_clipped_image(
+    canvas: &Canvas,
+    img: &Image,
+    clip_rect: &Rect,
+    rect: &Rect,
+    sampling: SamplingOptions,
+) {
+    canvas.save();
+    canvas.clip_rect(clip_rect, None, None);
+    canvas.draw_image_rect_with_sampling_options(img, None, rect, sampling, &Paint::default());
+    canvas.restore();
+}
+
 fn load_image_from_rgba_bytes(
     data: &[u8],
     stride: usize,
@@ -171,21 +1079,5 @@ fn load_image_from_rgba_bytes(
     height: usize,
     color_type: ColorType,
 ) -> Image {
-    let width = width as i32;
-    let height = height as i32;
-    let image_info = ImageInfo::new(
-        (width, height),
-        color_type,
-        AlphaType::Unpremul,
-        ColorSpace::new_srgb(),
-    );
-    let mut bm = Bitmap::new();
-    let _ = bm.set_info(&image_info, stride);
-    bm.alloc_pixels();
-    unsafe {
-        let dest = bm.pixels();
-        let src = data.as_ptr() as *const c_void;
-        memcpy(dest, src, data.len() as size_t);
-    }
-    bm.as_image()
+    load_bitmap_from_rgba_bytes(data, stride, width, height, color_type).as_image()
 }
@@ -0,0 +1,280 @@
+use ffmpeg_next::ffi::{
+    av_buffersink_get_format, av_buffersink_get_h, av_buffersink_get_time_base,
+    av_buffersink_get_w, AVPixelFormat,
+};
+use ffmpeg_next::filter::Graph;
+use ffmpeg_next::format::Pixel;
+use ffmpeg_next::{decoder, filter, frame, Rational};
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeinterlaceMode {
+    /// Deinterlace once a frame flagged as interlaced is decoded
+    Auto,
+    On,
+    Off,
+}
+
+impl DeinterlaceMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        let mode = match value.trim().to_ascii_lowercase().as_str() {
+            "auto" => Self::Auto,
+            "on" => Self::On,
+            "off" => Self::Off,
+            _ => return None,
+        };
+        Some(mode)
+    }
+}
+
+/// Graph description for the decoded frames: the deinterlacer, if needed, followed by `spec`.
+/// The deinterlacer outputs one frame per field and takes the field order from each frame
+pub fn filter_chain(
+    deinterlace: DeinterlaceMode,
+    interlaced: bool,
+    spec: Option<&str>,
+) -> Option<String> {
+    let deinterlacer = match deinterlace {
+        DeinterlaceMode::On => Some("bwdif=mode=send_field:parity=auto:deint=all"),
+        // Progressive frames of mixed content are passed through unchanged
+        DeinterlaceMode::Auto if interlaced => {
+            Some("bwdif=mode=send_field:parity=auto:deint=interlaced")
+        }
+        _ => None,
+    };
+    match (deinterlacer, spec) {
+        (Some(deinterlacer), Some(spec)) => Some(format!("{},{}", deinterlacer, spec)),
+        (deinterlacer, spec) => deinterlacer.or(spec).map(str::to_string),
+    }
+}
+
+/// libavfilter graph applied to decoded frames, e.g. `yadif,eq=contrast=1.2`
+pub struct VideoFilter {
+    graph: Graph,
+    spec: String,
+    input: InputFormat,
+    /// Time base of the filtered frames, e.g. halved by `yadif=1`
+    output_time_base: Rational,
+    flushed: bool,
+}
+
+/// Frame parameters the graph was configured for
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct InputFormat {
+    format: Pixel,
+    width: u32,
+    height: u32,
+    time_base: Rational,
+}
+
+/// Parameters of the frames fed to a graph
+#[derive(Clone, Copy, Debug)]
+pub struct VideoFormat {
+    pub format: Pixel,
+    pub width: u32,
+    pub height: u32,
+    pub aspect_ratio: Rational,
+}
+
+impl VideoFormat {
+    pub fn of_frame(frame: &frame::Video) -> Self {
+        Self {
+            format: frame.format(),
+            width: frame.width(),
+            height: frame.height(),
+            aspect_ratio: frame.aspect_ratio(),
+        }
+    }
+
+    /// Format of the frames the decoder produces, as opposed to those leaving the filters
+    pub fn of_decoder(decoder: &decoder::Video) -> Self {
+        Self {
+            format: decoder.format(),
+            width: decoder.width(),
+            height: decoder.height(),
+            aspect_ratio: decoder.aspect_ratio(),
+        }
+    }
+}
+
+/// Stream a user filter applies to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterKind {
+    Video,
+    Audio,
+}
+
+/// A graph failed while playing and was removed, playback goes on unfiltered
+#[derive(Debug)]
+pub struct FilterFailed {
+    pub kind: FilterKind,
+    pub spec: String,
+    pub message: String,
+}
+
+impl Display for FilterFailed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            FilterKind::Video => "video",
+            FilterKind::Audio => "audio",
+        };
+        write!(f, "{} filter {} failed: {}", kind, self.spec, self.message)
+    }
+}
+
+impl std::error::Error for FilterFailed {}
+
+impl VideoFilter {
+    /// Build a graph from `spec` for frames like `frame`
+    pub fn new(
+        spec: &str,
+        frame: &frame::Video,
+        time_base: Rational,
+    ) -> Result<Self, anyhow::Error> {
+        Self::with_format(spec, VideoFormat::of_frame(frame), time_base)
+    }
+
+    /// Build a graph from `spec` for frames of `video_format`
+    pub fn with_format(
+        spec: &str,
+        video_format: VideoFormat,
+        time_base: Rational,
+    ) -> Result<Self, anyhow::Error> {
+        let input = InputFormat {
+            format: video_format.format,
+            width: video_format.width,
+            height: video_format.height,
+            time_base,
+        };
+        let aspect_ratio = match video_format.aspect_ratio {
+            r if r.numerator() > 0 && r.denominator() > 0 => r,
+            _ => Rational::new(1, 1),
+        };
+        let args = format!(
+            "video_size={}x{}:pix_fmt={}:time_base={}/{}:pixel_aspect={}/{}",
+            input.width,
+            input.height,
+            AVPixelFormat::from(input.format) as i32,
+            time_base.numerator(),
+            time_base.denominator(),
+            aspect_ratio.numerator(),
+            aspect_ratio.denominator(),
+        );
+        let mut graph = Graph::new();
+        graph.add(&filter::find("buffer").unwrap(), "in", &args)?;
+        graph.add(&filter::find("buffersink").unwrap(), "out", "")?;
+        graph.output("in", 0)?.input("out", 0)?.parse(spec)?;
+        graph.validate()?;
+        let output_time_base =
+            unsafe { av_buffersink_get_time_base(graph.get("out").unwrap().as_ptr()) }.into();
+        Ok(Self {
+            graph,
+            spec: spec.to_string(),
+            input,
+            output_time_base,
+            flushed: false,
+        })
+    }
+
+    /// Build `spec` for typical frames, to report an invalid description before the frames
+    /// of a source are known
+    pub fn check(spec: &str) -> Result<(), anyhow::Error> {
+        let format = VideoFormat {
+            format: Pixel::YUV420P,
+            width: 640,
+            height: 360,
+            aspect_ratio: Rational::new(1, 1),
+        };
+        Self::with_format(spec, format, Rational::new(1, 25))?;
+        Ok(())
+    }
+
+    /// Whether the graph has to be rebuilt for `frame`, e.g. after a resolution change
+    pub fn accepts(&self, frame: &frame::Video, time_base: Rational) -> bool {
+        self.input.format == frame.format()
+            && self.input.width == frame.width()
+            && self.input.height == frame.height()
+            && self.input.time_base == time_base
+    }
+
+    pub fn spec(&self) -> &str {
+        &self.spec
+    }
+
+    /// Pixel format, width and height of the filtered frames
+    pub fn output_format(&self) -> (Pixel, u32, u32) {
+        let sink = self.graph.get("out").unwrap();
+        unsafe {
+            let format: AVPixelFormat =
+                std::mem::transmute(av_buffersink_get_format(sink.as_ptr()));
+            let width = av_buffersink_get_w(sink.as_ptr()) as u32;
+            let height = av_buffersink_get_h(sink.as_ptr()) as u32;
+            (format.into(), width, height)
+        }
+    }
+
+    pub fn push(&mut self, frame: &frame::Video) -> Result<(), anyhow::Error> {
+        self.graph.get("in").unwrap().source().add(frame)?;
+        Ok(())
+    }
+
+    /// Signal the end of input so that frames held back by the graph are released, false if
+    /// it was already flushed
+    pub fn flush(&mut self) -> bool {
+        if self.flushed {
+            return false;
+        }
+        self.flushed = true;
+        self.graph.get("in").unwrap().source().flush().is_ok()
+    }
+
+    /// Time base of the filtered frames, finer than the input one for filters such as
+    /// `yadif=1` that output one frame per field
+    pub fn output_time_base(&self) -> Rational {
+        self.output_time_base
+    }
+
+    /// Next filtered frame with its pts in `output_time_base`, None until the graph has
+    /// received enough input
+    pub fn pull(&mut self) -> Option<frame::Video> {
+        let mut filtered = frame::Video::empty();
+        self.graph
+            .get("out")
+            .unwrap()
+            .sink()
+            .frame(&mut filtered)
+            .ok()?;
+        Some(filtered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field_rate_output_keeps_distinct_timestamps() {
+        ffmpeg_next::init().unwrap();
+        let time_base = Rational::new(1, 25);
+        let mut frame = frame::Video::new(Pixel::YUV420P, 64, 48);
+        let mut filter = VideoFilter::new("yadif=mode=send_field", &frame, time_base).unwrap();
+        assert_eq!(filter.output_time_base(), Rational::new(1, 50));
+        let mut pts = Vec::new();
+        for index in 0..4 {
+            frame.set_pts(Some(index));
+            filter.push(&frame).unwrap();
+            pts.extend(std::iter::from_fn(|| filter.pull()).map(|f| f.pts().unwrap()));
+        }
+        filter.flush();
+        pts.extend(std::iter::from_fn(|| filter.pull()).map(|f| f.pts().unwrap()));
+        assert_eq!(pts.len(), 8);
+        assert!(pts.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", pts);
+    }
+
+    #[test]
+    fn check_reports_invalid_descriptions() {
+        ffmpeg_next::init().unwrap();
+        assert!(VideoFilter::check("eq=contrast=1.2,hflip").is_ok());
+        assert!(VideoFilter::check("no_such_filter").is_err());
+    }
+}
@@ -1,22 +1,267 @@
 declare class VideoElement extends Element{
     constructor();
+    #private;
     setSrc(src: any): void;
-    play(): void;
+    /**
+     * Play media held in memory
+     * @param buffer {ArrayBuffer}
+     */
+    setSrcBuffer(buffer: ArrayBuffer): void;
+    /**
+     * Start a source fed with appendBuffer, like a MediaSource
+     */
+    openStream(): void;
+    /**
+     * Append bytes to the source started with openStream
+     * @param buffer {ArrayBuffer}
+     */
+    appendBuffer(buffer: ArrayBuffer): void;
+    /**
+     * Signal that no more bytes will be appended
+     */
+    endOfStream(): void;
+    /**
+     * Load a source
+     * @param src {string}
+     * @returns {Promise<void>} resolved when metadata has been loaded
+     */
+    load(src: string): Promise<void>;
+    /**
+     *
+     * @returns {Promise<void>} resolved when the first frame has been presented
+     * Resolved as well when a newer play supersedes it. Rejected when the source is
+     * stopped, replaced or fails before a frame is presented, or when no video is loaded
+     */
+    play(): Promise<void>;
     pause(): void;
     stop(): void;
+    /**
+     * Stop playback and background jobs and release the audio device, e.g. before removing
+     * the element. Loading a new source starts over
+     */
+    destroy(): void;
+    /**
+     * @returns {"idle" | "loading" | "paused" | "playing" | "seeking" | "ended" | "error"}
+     */
+    get state(): "idle" | "loading" | "paused" | "playing" | "seeking" | "ended" | "error";
+    /**
+     * @returns {number} seconds
+     */
+    get currentTime(): number;
+    /**
+     * @returns {number} seconds
+     */
+    get duration(): number;
+    /**
+     * @returns {[number, number][]} loaded time ranges in seconds
+     */
+    get buffered(): [number, number][];
+    /**
+     * @returns {string | null} message of the last error
+     */
+    get error(): string | null;
+    /**
+     * @param value {number} 0-1
+     */
+    set volume(value: number);
+    /**
+     * @returns {number} 0-1
+     */
+    get volume(): number;
+    /**
+     * @returns {number} playback speed, 1 for normal speed
+     */
+    get playbackRate(): number;
     /**
      *
      * @param time {number}
+     * @returns {Promise<void>} resolved when the frame at the target time has been presented
+     * Resolved as well when a newer seek supersedes it. Rejected when the source is not
+     * seekable, is stopped or replaced first, or when no video is loaded
      */
-    seek(time: number): void;
+    seek(time: number): Promise<void>;
+    /**
+     * Pause and show the next or previous frame
+     * @param delta {1 | -1}
+     * @returns {Promise<void>} resolved when the frame has been presented. Rejected when there
+     * is no such frame or no video is loaded
+     */
+    stepFrame(delta: 1 | -1): Promise<void>;
     /**
      *
-     * @param callback {(e: IEvent<{duration: number, width: number, height: number}>) => void}
+     * @param fit {"contain" | "cover" | "fill" | "none" | "scale-down"}
+     */
+    setObjectFit(fit: "contain" | "cover" | "fill" | "none" | "scale-down"): void;
+    /**
+     *
+     * @param position {string} e.g. "center", "left top", "25% 75%"
+     */
+    setObjectPosition(position: string): void;
+    /**
+     * Filter used to scale frames to the view. "lanczos" pre-scales with Lanczos, any scaling
+     * left at draw time uses a Catmull-Rom cubic as an approximation
+     * @param quality {"fast-bilinear" | "bilinear" | "bicubic" | "lanczos"}
+     */
+    setScaleQuality(quality: "fast-bilinear" | "bilinear" | "bicubic" | "lanczos"): void;
+    /**
+     * Image shown until playback starts
+     * @param path {string} empty to clear
+     */
+    setPoster(path: string): void;
+    /**
+     * Time of the frame previewed after loading, takes effect on the next setSrc
+     * @param time {number} negative to preview the first frame
+     */
+    setPreviewTime(time: number): void;
+    /**
+     * Save the current frame at source resolution
+     * @param path {string}
+     * @param format {"png" | "jpeg" | "webp"}
+     * @param quality {number} 0-100, ignored by png
+     * @param burnSubtitles {boolean} draw the subtitle shown at the frame's time onto it
+     * @returns {Promise<string>} the written path
+     */
+    captureFrame(
+        path: string,
+        format?: "png" | "jpeg" | "webp",
+        quality?: number,
+        burnSubtitles?: boolean,
+    ): Promise<string>;
+    /**
+     * Write a sprite sheet of evenly spaced keyframes for seek bar previews,
+     * cancelled when the source changes
+     * @param options {{output: string, count?: number, tileWidth?: number, columns?: number}}
+     * @returns {Promise<{path: string, tile_width: number, tile_height: number, columns: number, thumbnails: {time: number, x: number, y: number}[]}>}
+     */
+    generateThumbnails(options: {
+        output: string;
+        count?: number;
+        tileWidth?: number;
+        columns?: number;
+    }): Promise<{
+        path: string;
+        tile_width: number;
+        tile_height: number;
+        columns: number;
+        thumbnails: {
+            time: number;
+            x: number;
+            y: number;
+        }[];
+    }>;
+    /**
+     * Save a range of the current source to an mp4 or mkv file, replacing a running export.
+     * Mode "copy" remuxes from the keyframe before start, "encode" re-encodes with the active
+     * video filter
+     * @param options {{output: string, start: number, end: number, mode?: "copy" | "encode", onProgress?: (progress: number) => void}}
+     * @returns {Promise<string>} the written path
+     */
+    exportClip(options: {
+        output: string;
+        start: number;
+        end: number;
+        mode?: "copy" | "encode";
+        onProgress?: (progress: number) => void;
+    }): Promise<string>;
+    /**
+     * Stop the running export, its promise is rejected
+     */
+    cancelExport(): void;
+    /**
+     * Save a range of the current source as an animated gif or webp, replacing a running
+     * animation export. Gif frames are quantised to a palette generated from the whole range
+     * @param options {{output: string, start: number, end: number, fps?: number, width?: number, format?: "gif" | "webp", onProgress?: (progress: number) => void}}
+     * @returns {Promise<string>} the written path
+     */
+    exportAnimation(options: {
+        output: string;
+        start: number;
+        end: number;
+        fps?: number;
+        width?: number;
+        format?: "gif" | "webp";
+        onProgress?: (progress: number) => void;
+    }): Promise<string>;
+    /**
+     * Stop the running animation export, its promise is rejected
+     */
+    cancelAnimation(): void;
+    /**
+     * Timeouts for opening a source and for a single network read, 0 to disable.
+     * Takes effect on the next source
+     * @param openTimeout {number} seconds
+     * @param readTimeout {number} seconds
+     */
+    setNetworkTimeouts(openTimeout: number, readTimeout: number): void;
+    /**
+     * Live streams are played with low latency, without duration or seeking,
+     * and reconnected when lost. Takes effect on the next source
+     * @param mode {"auto" | "on" | "off"}
+     */
+    setLiveMode(mode: "auto" | "on" | "off"): void;
+    /**
+     * Play a variant listed in loadedmetadata, -1 to select automatically from the throughput
+     * @param id {number}
+     */
+    setVariant(id: number): void;
+    /**
+     * Filter the video with a libavfilter graph, e.g. "yadif,eq=contrast=1.2", empty to remove it.
+     * An invalid description fires error
+     * @param spec {string}
+     */
+    setVideoFilter(spec: string): void;
+    /**
+     * Fire audiolevels every intervalMs (0 to stop) with per-channel peak and RMS levels and,
+     * if bands is not 0, a spectrum of that many bands
+     * @param intervalMs {number}
+     * @param bands {number}
+     */
+    setAudioLevels(intervalMs: number, bands: number): void;
+    /**
+     * Shift the audio against the video, positive plays it later and negative earlier
+     * @param ms {number}
+     */
+    setAudioDelay(ms: number): void;
+    /**
+     * Filter the audio with a libavfilter graph, e.g. "dynaudnorm" for night mode or
+     * "equalizer=f=100:t=q:w=1:g=4", empty to remove it. An invalid description fires error
+     * @param spec {string}
+     */
+    setAudioFilter(spec: string): void;
+    /**
+     * Deinterlace to field rate: "auto" once interlaced frames are detected, "on" or "off"
+     * @param mode {string}
+     */
+    setDeinterlace(mode: "auto" | "on" | "off"): void;
+    /**
+     * Restart from the beginning when the end is reached
+     * @param value {boolean}
+     */
+    setLoop(value: boolean): void;
+    /**
+     * Repeat the range between start and end (A-B repeat)
+     * @param start {number} seconds
+     * @param end {number} seconds
+     */
+    setLoopRange(start: number, end: number): void;
+    clearLoopRange(): void;
+    /**
+     *
+     * @param callback {(e: IEvent<{duration: number, width: number, height: number, seekable: boolean, live: boolean, variants: {id: number, bandwidth: number, width: number, height: number, codecs: string}[]}>) => void}
      */
     bindLoadedMetaData(callback: (e: IEvent<{
         duration: number;
         width: number;
         height: number;
+        seekable: boolean;
+        live: boolean;
+        variants: {
+            id: number;
+            bandwidth: number;
+            width: number;
+            height: number;
+            codecs: string;
+        }[];
     }>) => void): void;
     /**
      *
@@ -34,4 +279,107 @@ declare class VideoElement extends Element{
      * @param callback {() => void}
      */
     bindStop(callback: () => void): void;
+    /**
+     *
+     * @param callback {(e: IEvent<string>) => void}
+     */
+    bindError(callback: (e: IEvent<string>) => void): void;
+    /**
+     * Fired when playback reaches the end of the media, after all audio has been played
+     * @param callback {() => void}
+     */
+    bindEnded(callback: () => void): void;
+    /**
+     * Fired when playback stalls waiting for network data or appended bytes
+     * @param callback {() => void}
+     */
+    bindWaiting(callback: () => void): void;
+    /**
+     * Fired when data has been appended after waiting
+     * @param callback {() => void}
+     */
+    bindCanPlay(callback: () => void): void;
+    /**
+     * Fired when playback resumes after waiting for network data
+     * @param callback {() => void}
+     */
+    bindPlaying(callback: () => void): void;
+    /**
+     * Fired when the downloaded range of a network source grows
+     * @param callback {(e: IEvent<[number, number][]>) => void}
+     */
+    bindBuffered(callback: (e: IEvent<[number, number][]>) => void): void;
+    /**
+     *
+     * @param callback {(e: IEvent<{pts: number, time: number, frame: number}>) => void}
+     */
+    bindFrameStep(callback: (e: IEvent<{
+        pts: number;
+        time: number;
+        frame: number;
+    }>) => void): void;
+    /**
+     * Levels of the audio being played, amplitudes in 0-1, see setAudioLevels
+     * @param callback {(e: IEvent<{peak: number[], rms: number[], spectrum: number[]}>) => void}
+     */
+    bindAudioLevels(callback: (e: IEvent<{
+        peak: number[];
+        rms: number[];
+        spectrum: number[];
+    }>) => void): void;
+    /**
+     *
+     * @param callback {(e: IEvent<number>) => void} progress in 0-1
+     */
+    bindThumbnailProgress(callback: (e: IEvent<number>) => void): void;
+}
+
+interface ProbeInfo {
+    /** Demuxer short names, e.g. "mov,mp4,m4a,3gp,3g2,mj2" */
+    format: string;
+    format_long_name: string;
+    /** Seconds, null when unknown, e.g. for live streams */
+    duration: number | null;
+    /** Bits per second of the whole source */
+    bit_rate: number | null;
+    tags: Record<string, string>;
+    chapters: {
+        id: number;
+        start: number;
+        end: number;
+        title: string | null;
+    }[];
+    /** Fields that do not apply to the stream kind are null */
+    streams: {
+        index: number;
+        kind: "video" | "audio" | "subtitle" | "data" | "attachment" | "unknown";
+        codec: string;
+        profile: string | null;
+        bit_rate: number | null;
+        duration: number | null;
+        language: string | null;
+        tags: Record<string, string>;
+        width: number | null;
+        height: number | null;
+        fps: number | null;
+        /** Sample aspect ratio as [numerator, denominator] */
+        sar: [number, number] | null;
+        pixel_format: string | null;
+        color_range: string | null;
+        color_space: string | null;
+        color_primaries: string | null;
+        color_transfer: string | null;
+        sample_rate: number | null;
+        channels: number | null;
+        channel_layout: string | null;
+        sample_format: string | null;
+    }[];
 }
+
+/**
+ * Read the container and stream metadata of a file or URL without starting playback.
+ * The source is opened in the background
+ * @param path {string}
+ * @returns {Promise<ProbeInfo>} rejected when the source cannot be opened
+ */
+declare function probe(path: string): Promise<ProbeInfo>;
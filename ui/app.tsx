@@ -9,6 +9,7 @@ const App = () => {
 
     const videoRef = useRef<VideoElement>(null);
     const [duration, setDuration] = useState(0);
+    const [live, setLive] = useState(false);
     const [currentTime, setCurrentTime] = useState(0);
     const [videoDir, setVideoDir] = useState("");
     const [videoFiles, setVideoFiles] = useState([]);
@@ -57,10 +58,8 @@ const App = () => {
             return;
         }
         const video = videoRef.current = new VideoElement();
-        let playing = false;
-        function updatePlaying(value) {
-            playing = value;
-            setPlaying(value);
+        function updatePlaying() {
+            setPlaying(video.state === "playing");
         }
         video.style = {
             width: '100%',
@@ -68,27 +67,31 @@ const App = () => {
         };
         console.log('video', video);
         video.bindClick(e => {
-            if (playing) {
+            if (video.state === "playing") {
                 video.pause();
             } else {
-                video.play();
+                video.play().catch(e => console.log("play failed", e));
             }
         })
         video.bindLoadedMetaData(e => {
             console.log('loaded', e.target == video);
             setDuration(e.detail.duration);
-            video.play();
+            setLive(e.detail.live);
+            video.play().catch(e => console.log("play failed", e));
         })
         video.bindPlay(() => {
             console.log('playing');
-            updatePlaying(true);
+            updatePlaying();
         })
         video.bindPause(() => {
-            updatePlaying(false);
+            updatePlaying();
         })
         video.bindStop(() => {
             console.log("stopped")
-            updatePlaying(false);
+            updatePlaying();
+        })
+        video.bindEnded(() => {
+            updatePlaying();
             onNext();
         })
         video.bindProgress(e => {
@@ -98,14 +101,9 @@ const App = () => {
         wrapperRef.addChild(video);
         video.setSrc(videoPath);
         return () => {
-            try {
-                //TODO no rebind?
-                video.bindStop(() => {});
-                video.stop();
-            } finally {
-                wrapperRef.removeChild(video);
-                videoRef.current = null;
-            }
+            video.destroy();
+            wrapperRef.removeChild(video);
+            videoRef.current = null;
         }
     }, [videoPath]);
 
@@ -114,7 +112,7 @@ const App = () => {
     function onSeek(value: number) {
         const time = value / 100 * duration;
         console.log("seek", time);
-        videoRef.current?.seek(time);
+        videoRef.current?.seek(time).catch(e => console.log("seek failed", e));
     }
 
     const playIcon = require('./assets/play.svg');
@@ -131,7 +129,7 @@ const App = () => {
         if (playing) {
             videoRef.current?.pause();
         } else {
-            videoRef.current?.play();
+            videoRef.current?.play().catch(e => console.log("play failed", e));
         }
     }
 
@@ -229,12 +227,12 @@ const App = () => {
                     </Container>
                 </Container>
                 <Container className={`main-panel ${fullscreen ? (mouseMoving ? 'main-panel-fullscreen-moving' : 'main-panel-fullscreen') : ''}`}>
-                    <Progress value={progressPercent} onChange={onSeek} />
+                    {!live && <Progress value={progressPercent} onChange={onSeek} />}
                     <Row className="control-panel">
                         {
                             videoPath &&
                             <Container className="control-left-side-panel">
-                                {formatHumanShortTime(currentTime) + ' / ' + formatHumanShortTime(duration)}
+                                {live ? 'LIVE' : formatHumanShortTime(currentTime) + ' / ' + formatHumanShortTime(duration)}
                             </Container>
                         }
                         <Row className="play-btn-group" style={{flex: 1}}>